@@ -1,4 +1,5 @@
-use crate::object::Object;
+use crate::eval::BUILTINS;
+use crate::object::{Captured, Object};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -10,8 +11,13 @@ pub struct Env {
 }
 
 impl Env {
+    /// A global environment, with every builtin procedure bound.
     pub fn new() -> Self {
-        Default::default()
+        let mut env = Env::default();
+        for name in BUILTINS {
+            env.set(name, Object::Builtin(name));
+        }
+        env
     }
 
     pub fn extend(parent: Rc<RefCell<Env>>) -> Self {
@@ -23,12 +29,53 @@ impl Env {
 
     pub fn get(&self, key: &str) -> Option<Object> {
         match self.vars.get(key) {
+            Some(Object::Lambda(lambda)) => Some(Object::Lambda(lambda.upgraded())),
             Some(val) => Some(val.clone()),
             None => self.parent.as_ref().and_then(|p| p.borrow().get(key)),
         }
     }
 
+    /// The names bound in this scope and every enclosing one.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.vars.keys().cloned().collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
     pub fn set(&mut self, name: &str, val: Object) {
+        let val = self.weaken(val);
         self.vars.insert(name.to_string(), val);
     }
+
+    /// A closure over this very scope is stored with a weak reference to it,
+    /// as otherwise the two would keep each other alive. `get` makes it
+    /// strong again.
+    fn weaken(&self, val: Object) -> Object {
+        match val {
+            Object::Lambda(mut lambda) => {
+                if let Captured::Strong(env) = &lambda.env {
+                    if std::ptr::eq(RefCell::as_ptr(env), self) {
+                        lambda.env = Captured::Weak(Rc::downgrade(env));
+                    }
+                }
+                Object::Lambda(lambda)
+            }
+            val => val,
+        }
+    }
+
+    /// Rebinds `name` in the innermost scope that defines it. Returns false,
+    /// leaving every scope untouched, if `name` is unbound.
+    pub fn update(&mut self, name: &str, val: Object) -> bool {
+        if self.vars.contains_key(name) {
+            self.set(name, val);
+            return true;
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().update(name, val),
+            None => false,
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+
+/// A region of the source text. `start` and `end` are byte offsets; `line`
+/// and `column` (both 1-based, column counted in characters) locate `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Builds the span covering `start..end` of `source`.
+    pub fn locate(source: &str, start: usize, end: usize) -> Self {
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = source[line_start..start].chars().count() + 1;
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    Lex(String),
+    Parse(String),
+    /// The input ended inside a form, so more lines may complete it.
+    Incomplete(String),
+    Syntax(String),
+    UnboundSymbol(String),
+    Arity {
+        name: String,
+        expected: String,
+        found: usize,
+    },
+    Type(String),
+    Range(String),
+    DivisionByZero,
+    /// Evaluation nested deeper than `eval::max_depth`.
+    RecursionDepth,
+    User(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LispError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+    /// The `Object::id`s of the forms being evaluated when the error was
+    /// raised, innermost first. The evaluator works on plain objects, so this
+    /// is how its errors are mapped back to a span once they reach code that
+    /// has the source.
+    pub forms: Vec<usize>,
+}
+
+impl LispError {
+    pub fn new(kind: ErrorKind) -> Self {
+        LispError {
+            kind,
+            span: None,
+            forms: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Records that the error happened while evaluating the form with the
+    /// `Object::id` `form`, unless its span is already known.
+    pub fn in_form(mut self, form: usize) -> Self {
+        if self.span.is_none() {
+            self.forms.push(form);
+        }
+        self
+    }
+
+    /// Whether the error only means the input ended too early, as opposed to
+    /// being malformed.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ErrorKind::Incomplete(_))
+    }
+
+    /// Formats the error followed by the offending line of `source` with the
+    /// span underlined. Errors without a span render as their message alone.
+    pub fn render(&self, source: &str) -> String {
+        let span = match self.span {
+            Some(span) if span.start <= source.len() => span,
+            _ => return format!("{}", self),
+        };
+
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        let text = &source[line_start..line_end];
+        let end = span.end.clamp(span.start, line_end);
+        let width = source[span.start..end].chars().count().max(1);
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{}\n{} --> line {}, column {}\n{} |\n{} | {}\n{} | {}{}",
+            self,
+            gutter,
+            span.line,
+            span.column,
+            gutter,
+            number,
+            text,
+            gutter,
+            " ".repeat(span.column - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::Lex(msg) => write!(f, "Tokenization error: {}", msg),
+            ErrorKind::Parse(msg) => write!(f, "Parse error: {}", msg),
+            ErrorKind::Incomplete(msg) => write!(f, "Incomplete input: {}", msg),
+            ErrorKind::Syntax(msg) => write!(f, "Syntax error: {}", msg),
+            ErrorKind::UnboundSymbol(s) => write!(f, "Unbound symbol: {}", s),
+            ErrorKind::Arity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Wrong number of arguments for {}: expected {}, found {}",
+                name, expected, found
+            ),
+            ErrorKind::Type(msg) => write!(f, "Type error: {}", msg),
+            ErrorKind::Range(msg) => write!(f, "Range error: {}", msg),
+            ErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            ErrorKind::RecursionDepth => write!(f, "Maximum recursion depth exceeded"),
+            ErrorKind::User(msg) => write!(f, "Error: {}", msg),
+        }
+    }
+}
+
+impl Error for LispError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let source = "(define x 1)\n(+ x y)";
+        let span = Span::locate(source, 18, 19);
+        assert_eq!(span.line, 2);
+        assert_eq!(span.column, 6);
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "(define x 1)\n(+ x yy)";
+        let err = LispError::new(ErrorKind::UnboundSymbol("yy".to_string()))
+            .with_span(Span::locate(source, 18, 20));
+        assert_eq!(
+            err.render(source),
+            "Unbound symbol: yy\n  --> line 2, column 6\n  |\n2 | (+ x yy)\n  |      ^^"
+        );
+    }
+}
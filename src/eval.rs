@@ -1,217 +1,1129 @@
 use crate::env::*;
+use crate::error::*;
+use crate::number;
 use crate::object::*;
 use crate::parser::*;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::rc::Rc;
 
-fn eval_binary_op(list: &Vec<Object>, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for infix operator".to_string());
-    }
-    let operator = list[0].clone();
-    let left = &eval_obj(&list[1].clone(), env)?;
-    let right = &eval_obj(&list[2].clone(), env)?;
-    match operator {
-        Object::Symbol(s) => match s.as_str() {
-            "+" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l + r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 + r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l + *r as f64)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l + r)),
-                (Object::String(l), Object::String(r)) => Ok(Object::String(l.to_string() + r)),
-                _ => Err(format!("Invalid types for + operator {} {}", left, right)),
-            },
-            "-" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l - r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 - r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l - *r as f64)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l - r)),
-                _ => Err(format!("Invalid types for - operator {} {}", left, right)),
-            },
-            "*" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l * r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 * r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l * *r as f64)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l * r)),
-                _ => Err(format!("Invalid types for * operator {} {}", left, right)),
-            },
-            "/" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l / r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 / r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l / *r as f64)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l / r)),
-                _ => Err(format!("Invalid types for / operator {} {}", left, right)),
-            },
-            "<" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l < r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool((*l as f64) < *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l < &(*r as f64))),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l < r)),
-                _ => Err(format!("Invalid types for < operator {} {}", left, right)),
-            },
-            ">" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l > r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool((*l as f64) > *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l > &(*r as f64))),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l > r)),
-                _ => Err(format!("Invalid types for > operator {} {}", left, right)),
-            },
-            "=" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l == r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool((*l as f64) == *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l == &(*r as f64))),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l == r)),
-                _ => Err(format!("Invalid types for = operator {} {}", left, right)),
-            },
-            "!=" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l != r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool((*l as f64) != *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l != &(*r as f64))),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l != r)),
-                _ => Err(format!("Invalid types for != operator {} {}", left, right)),
-            },
-            _ => Err(format!("Invalid infix operator: {}", s)),
-        },
-        _ => Err("Operator must be a symbol".to_string()),
+/// The stack one level of `eval_obj` nesting may use, measured on the
+/// deepest paths through the evaluator with room to spare. Unoptimized builds
+/// need several times as much.
+const STACK_PER_LEVEL: usize = if cfg!(debug_assertions) {
+    32 * 1024
+} else {
+    6 * 1024
+};
+
+/// The stack the binary evaluates with.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// The stack assumed for a thread whose size was never given, which is the
+/// least a Rust thread gets by default.
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+thread_local! {
+    /// Whether procedure calls are printed to stderr as they are made.
+    static TRACE: Cell<bool> = const { Cell::new(false) };
+    /// The number of `eval_obj` calls in progress.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// How deeply `eval_obj` may nest before evaluation fails instead of
+    /// overflowing the stack. Calls in tail position do not count.
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_STACK_SIZE / STACK_PER_LEVEL) };
+}
+
+/// Sets the depth limit of the current thread to suit a stack of `bytes`.
+pub fn set_stack_size(bytes: usize) {
+    MAX_DEPTH.set(bytes / STACK_PER_LEVEL);
+}
+
+/// How deeply evaluation may nest on the current thread. Parsing and
+/// printing, which take less stack per level, keep to the same limit.
+pub fn max_depth() -> usize {
+    MAX_DEPTH.get()
+}
+
+pub fn set_trace(on: bool) {
+    TRACE.set(on);
+}
+
+pub fn trace_enabled() -> bool {
+    TRACE.get()
+}
+
+/// The result of evaluating a single form. Forms whose value is the value of
+/// a sub-expression in tail position hand that expression back instead of
+/// evaluating it, so `eval_obj` can continue in a loop rather than recursing.
+/// The expression is a clone, so it comes with the `Object::id` of the
+/// original.
+enum Step {
+    Value(Object),
+    TailCall(Object, usize, Rc<RefCell<Env>>),
+}
+
+fn tail_call(obj: &Object, env: &Rc<RefCell<Env>>) -> Step {
+    Step::TailCall(obj.clone(), obj.id(), env.clone())
+}
+
+fn eval_binary_op(op: &str, left: &Object, right: &Object) -> Result<Object, LispError> {
+    match (op, left, right) {
+        ("+", Object::String(l), Object::String(r)) => Ok(Object::String(l.to_string() + r)),
+        ("quotient" | "rem" | "mod", _, _) => number::integer_division(op, left, right),
+        _ => number::arithmetic(op, left, right),
+    }
+}
+
+fn eval_comparison(op: &str, left: &Object, right: &Object) -> Result<bool, LispError> {
+    let ordering = number::compare(op, left, right)?;
+    Ok(match op {
+        "<" => ordering == Some(Ordering::Less),
+        ">" => ordering == Some(Ordering::Greater),
+        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        "=" => ordering == Some(Ordering::Equal),
+        _ => ordering != Some(Ordering::Equal),
+    })
+}
+
+fn check_arity(op: &str, args: &[Object], min: usize, max: Option<usize>) -> Result<(), LispError> {
+    if args.len() >= min && max.is_none_or(|max| args.len() <= max) {
+        return Ok(());
+    }
+    let expected = match max {
+        Some(max) if max == min => min.to_string(),
+        Some(max) => format!("{} to {}", min, max),
+        None => format!("at least {}", min),
+    };
+    Err(LispError::new(ErrorKind::Arity {
+        name: op.to_string(),
+        expected,
+        found: args.len(),
+    }))
+}
+
+/// Evaluates the numeric builtins. `+` and `*` fold from their identity, so
+/// they accept any number of arguments; `-` and `/` with one argument negate
+/// and take the reciprocal; comparisons hold when every adjacent pair of
+/// arguments satisfies them.
+fn eval_numeric_op(op: &str, args: Vec<Object>) -> Result<Object, LispError> {
+    match op {
+        "+" | "*" => {
+            let identity = Object::Integer(if op == "+" { 0 } else { 1 });
+            let (first, rest) = match args.split_first() {
+                Some((first, rest)) => (first, rest),
+                None => return Ok(identity),
+            };
+            match first {
+                Object::String(_) if op == "+" => {}
+                _ if number::is_number(first) => {}
+                _ => {
+                    return Err(LispError::new(ErrorKind::Type(format!(
+                        "Invalid type for {} {}",
+                        op, first
+                    ))))
+                }
+            }
+            rest.iter()
+                .try_fold(first.clone(), |acc, arg| eval_binary_op(op, &acc, arg))
+        }
+        "-" | "/" => {
+            check_arity(op, &args, 1, None)?;
+            if args.len() == 1 {
+                let identity = Object::Integer(if op == "-" { 0 } else { 1 });
+                return eval_binary_op(op, &identity, &args[0]);
+            }
+            args[1..]
+                .iter()
+                .try_fold(args[0].clone(), |acc, arg| eval_binary_op(op, &acc, arg))
+        }
+        "quotient" | "rem" | "mod" => {
+            check_arity(op, &args, 2, Some(2))?;
+            eval_binary_op(op, &args[0], &args[1])
+        }
+        "abs" | "exact->inexact" | "inexact->exact" => {
+            check_arity(op, &args, 1, Some(1))?;
+            match op {
+                "abs" => number::abs(&args[0]),
+                "exact->inexact" => number::exact_to_inexact(&args[0]),
+                _ => number::inexact_to_exact(&args[0]),
+            }
+        }
+        "min" | "max" => {
+            check_arity(op, &args, 1, None)?;
+            let mut result = args[0].clone();
+            for arg in &args[1..] {
+                let replace = if op == "min" { "<" } else { ">" };
+                if eval_comparison(replace, arg, &result)? {
+                    result = arg.clone();
+                }
+            }
+            // A single inexact argument makes the result inexact.
+            if args.iter().any(|arg| matches!(arg, Object::Float(_))) {
+                return number::exact_to_inexact(&result);
+            }
+            eval_comparison("=", &result, &result)?;
+            Ok(result)
+        }
+        _ => {
+            check_arity(op, &args, 1, None)?;
+            for pair in args.windows(2) {
+                if !eval_comparison(op, &pair[0], &pair[1])? {
+                    return Ok(Object::Bool(false));
+                }
+            }
+            // Still type-check a lone argument.
+            eval_comparison(op, &args[0], &args[0])?;
+            Ok(Object::Bool(true))
+        }
+    }
+}
+
+fn proper_list<'a>(op: &str, obj: &'a Object) -> Result<&'a List, LispError> {
+    match obj {
+        Object::List(list) => Ok(list),
+        _ => Err(LispError::new(ErrorKind::Type(format!(
+            "{} expects a list, found {}",
+            op, obj
+        )))),
+    }
+}
+
+/// Evaluates the pair and list builtins.
+fn eval_list_op(op: &str, mut args: Vec<Object>) -> Result<Object, LispError> {
+    match op {
+        "cons" => {
+            check_arity(op, &args, 2, Some(2))?;
+            let cdr = args.pop().unwrap();
+            let car = args.pop().unwrap();
+            Ok(Object::cons(car, cdr))
+        }
+        "car" | "cdr" => {
+            check_arity(op, &args, 1, Some(1))?;
+            match &args[0] {
+                Object::List(list) if !list.is_empty() => Ok(if op == "car" {
+                    list[0].clone()
+                } else {
+                    Object::List(list.tail())
+                }),
+                Object::Pair(pair) => Ok(if op == "car" {
+                    pair.0.clone()
+                } else {
+                    pair.1.clone()
+                }),
+                arg => Err(LispError::new(ErrorKind::Type(format!(
+                    "{} expects a pair, found {}",
+                    op, arg
+                )))),
+            }
+        }
+        "list" => Ok(Object::List(args.into())),
+        "null?" | "pair?" => {
+            check_arity(op, &args, 1, Some(1))?;
+            Ok(Object::Bool(match &args[0] {
+                Object::List(list) => list.is_empty() == (op == "null?"),
+                Object::Pair(_) => op == "pair?",
+                _ => false,
+            }))
+        }
+        "length" => {
+            check_arity(op, &args, 1, Some(1))?;
+            Ok(Object::Integer(proper_list(op, &args[0])?.len() as i64))
+        }
+        "reverse" => {
+            check_arity(op, &args, 1, Some(1))?;
+            let list = proper_list(op, &args[0])?;
+            Ok(Object::List(
+                list.iter()
+                    .fold(List::default(), |tail, item| List::cons(item.clone(), tail)),
+            ))
+        }
+        "append" => {
+            // Every argument but the last must be a list; the last one becomes
+            // the tail of the result as is, so it may be any object.
+            let mut result = match args.pop() {
+                Some(last) => last,
+                None => return Ok(Object::List(List::default())),
+            };
+            for arg in args.iter().rev() {
+                let items: Vec<&Object> = proper_list(op, arg)?.iter().collect();
+                result = items
+                    .into_iter()
+                    .rev()
+                    .fold(result, |cdr, car| Object::cons(car.clone(), cdr));
+            }
+            Ok(result)
+        }
+        _ => {
+            check_arity(op, &args, 2, Some(2))?;
+            let list = proper_list(op, &args[0])?;
+            match &args[1] {
+                Object::Integer(k) if *k >= 0 && (*k as usize) < list.len() => {
+                    Ok(list[*k as usize].clone())
+                }
+                Object::Integer(k) => Err(LispError::new(ErrorKind::Range(format!(
+                    "{} index {} out of range for a list of length {}",
+                    op,
+                    k,
+                    list.len()
+                )))),
+                arg => Err(LispError::new(ErrorKind::Type(format!(
+                    "{} expects an integer index, found {}",
+                    op, arg
+                )))),
+            }
+        }
     }
 }
 
-fn eval_define(list: &Vec<Object>, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
+fn eval_define(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
     if list.len() != 3 {
-        return Err("Invalid number of arguments for define".to_string());
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "define".to_string(),
+            expected: "2".to_string(),
+            found: list.len() - 1,
+        }));
     }
 
     let sym = match &list[1] {
         Object::Symbol(s) => s.clone(),
-        _ => return Err("Invalid define".to_string()),
+        _ => {
+            return Err(LispError::new(ErrorKind::Syntax(
+                "Invalid define".to_string(),
+            )))
+        }
     };
     let val = eval_obj(&list[2], env)?;
     env.borrow_mut().set(&sym, val);
     Ok(Object::Void)
 }
 
-fn eval_if(list: &Vec<Object>, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 4 {
-        return Err("Invalid number of arguments for if statement".to_string());
+fn eval_set(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    if list.len() != 3 {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "set!".to_string(),
+            expected: "2".to_string(),
+            found: list.len() - 1,
+        }));
+    }
+
+    let sym = match &list[1] {
+        Object::Symbol(s) => s.clone(),
+        _ => {
+            return Err(LispError::new(ErrorKind::Syntax(
+                "Invalid set!".to_string(),
+            )))
+        }
+    };
+    let val = eval_obj(&list[2], env)?;
+    if !env.borrow_mut().update(&sym, val) {
+        return Err(LispError::new(ErrorKind::UnboundSymbol(sym)));
+    }
+    Ok(Object::Void)
+}
+
+fn eval_condition(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<bool, LispError> {
+    Ok(eval_obj(obj, env)?.is_truthy())
+}
+
+/// Evaluates `(if test then else)`. Without an `else` branch the form is
+/// `Void` when `test` does not hold.
+fn eval_if(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    if !(3..=4).contains(&list.len()) {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "if".to_string(),
+            expected: "2 to 3".to_string(),
+            found: list.len() - 1,
+        }));
+    }
+
+    if eval_condition(&list[1], env)? {
+        Ok(tail_call(&list[2], env))
+    } else if let Some(branch) = list.get(3) {
+        Ok(tail_call(branch, env))
+    } else {
+        Ok(Step::Value(Object::Void))
+    }
+}
+
+/// Evaluates `cond`. A clause is `(test body ...)`, `(test)`, which yields
+/// the value of `test`, `(test => proc)`, which calls `proc` with it, or a
+/// final `(else body ...)`. With no matching clause the result is `Void`.
+fn eval_cond(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    for (i, clause) in list.iter().skip(1).enumerate() {
+        let clause = match clause {
+            Object::List(clause) if !clause.is_empty() => clause,
+            _ => {
+                return Err(LispError::new(ErrorKind::Syntax(format!(
+                    "Invalid cond clause: {}",
+                    clause
+                ))))
+            }
+        };
+
+        if clause[0] == Object::Symbol("else".to_string()) {
+            if i != list.len() - 2 {
+                return Err(LispError::new(ErrorKind::Syntax(
+                    "else must be the last cond clause".to_string(),
+                )));
+            }
+            return eval_body(&clause.tail(), env);
+        }
+
+        let test = eval_obj(&clause[0], env)?;
+        if !test.is_truthy() {
+            continue;
+        }
+        if clause.len() == 1 {
+            return Ok(Step::Value(test));
+        }
+        if clause[1] == Object::Symbol("=>".to_string()) {
+            if clause.len() != 3 {
+                return Err(LispError::new(ErrorKind::Syntax(
+                    "=> must be followed by exactly one expression".to_string(),
+                )));
+            }
+            let func = eval_obj(&clause[2], env)?;
+            return apply(func, &clause[2].to_string(), vec![test]);
+        }
+        return eval_body(&clause.tail(), env);
+    }
+    Ok(Step::Value(Object::Void))
+}
+
+/// Evaluates `(case key ((datum ...) body ...) ... (else body ...))`, running
+/// the body of the first clause that lists a datum equal to `key`.
+fn eval_case(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    if list.len() < 2 {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "case".to_string(),
+            expected: "at least 1".to_string(),
+            found: 0,
+        }));
+    }
+
+    let key = eval_obj(&list[1], env)?;
+    for (i, clause) in list.iter().skip(2).enumerate() {
+        let clause = match clause {
+            Object::List(clause) if !clause.is_empty() => clause,
+            _ => {
+                return Err(LispError::new(ErrorKind::Syntax(format!(
+                    "Invalid case clause: {}",
+                    clause
+                ))))
+            }
+        };
+
+        let matched = match &clause[0] {
+            Object::Symbol(s) if s == "else" => {
+                if i != list.len() - 3 {
+                    return Err(LispError::new(ErrorKind::Syntax(
+                        "else must be the last case clause".to_string(),
+                    )));
+                }
+                true
+            }
+            Object::List(data) => data.iter().any(|datum| *datum == key),
+            other => {
+                return Err(LispError::new(ErrorKind::Syntax(format!(
+                    "Invalid case clause data: {}",
+                    other
+                ))))
+            }
+        };
+        if matched {
+            return eval_body(&clause.tail(), env);
+        }
     }
+    Ok(Step::Value(Object::Void))
+}
 
-    let cond_obj = eval_obj(&list[1], env)?;
-    let cond = match cond_obj {
-        Object::Bool(b) => b,
-        _ => return Err("Condition must be a boolean".to_string()),
+/// Evaluates `and` and `or` left to right, stopping at the first false
+/// (`and`) or true (`or`) value and returning it. The last operand is in
+/// tail position. `(and)` is `#t` and `(or)` is `#f`.
+fn eval_and_or(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let is_and = list[0] == Object::Symbol("and".to_string());
+    let mut operands = list.iter().skip(1);
+    let mut last = match operands.next() {
+        Some(obj) => obj,
+        None => return Ok(Step::Value(Object::Bool(is_and))),
     };
+    for obj in operands {
+        let val = eval_obj(last, env)?;
+        if val.is_truthy() != is_and {
+            return Ok(Step::Value(val));
+        }
+        last = obj;
+    }
+    Ok(tail_call(last, env))
+}
+
+/// `(when test body ...)` evaluates the body when `test` holds, `unless`
+/// when it does not. Either way the form is `Void` if the body is skipped.
+fn eval_when(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    if list.len() < 2 {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: list[0].to_string(),
+            expected: "at least 1".to_string(),
+            found: 0,
+        }));
+    }
 
-    if cond {
-        return eval_obj(&list[2], env);
+    let expected = list[0] == Object::Symbol("when".to_string());
+    if eval_condition(&list[1], env)? == expected {
+        eval_body(&list.skip(2), env)
     } else {
-        return eval_obj(&list[3], env);
+        Ok(Step::Value(Object::Void))
+    }
+}
+
+fn eval_quote(list: &List) -> Result<Object, LispError> {
+    if list.len() != 2 {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "quote".to_string(),
+            expected: "1".to_string(),
+            found: list.len() - 1,
+        }));
+    }
+    Ok(list[1].clone())
+}
+
+fn eval_quasiquote(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    if list.len() != 2 {
+        return Err(LispError::new(ErrorKind::Arity {
+            name: "quasiquote".to_string(),
+            expected: "1".to_string(),
+            found: list.len() - 1,
+        }));
+    }
+    quasiquote(&list[1], 1, env)
+}
+
+/// Returns the operand of `(name x)`.
+fn special_form_arg<'a>(obj: &'a Object, name: &str) -> Option<&'a Object> {
+    match obj {
+        Object::List(list) if list.len() == 2 && list[0] == Object::Symbol(name.to_string()) => {
+            Some(&list[1])
+        }
+        _ => None,
     }
 }
 
-fn eval_function_definition(list: &[Object]) -> Result<Object, String> {
-    let params = match &list[1] {
-        Object::List(list) => {
-            let mut params = Vec::new();
-            for param in list {
-                match param {
-                    Object::Symbol(s) => params.push(s.clone()),
-                    _ => return Err("Invalid lambda parameter".to_string()),
+/// Expands a quasiquote template. `depth` counts the enclosing quasiquotes,
+/// so only unquotes that match the outermost one are evaluated.
+fn quasiquote(
+    template: &Object,
+    depth: usize,
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Object, LispError> {
+    if let Some(obj) = special_form_arg(template, "unquote") {
+        if depth == 1 {
+            return eval_obj(obj, env);
+        }
+        let inner = quasiquote(obj, depth - 1, env)?;
+        return Ok(Object::List(
+            vec![Object::Symbol("unquote".to_string()), inner].into(),
+        ));
+    }
+    if let Some(obj) = special_form_arg(template, "quasiquote") {
+        let inner = quasiquote(obj, depth + 1, env)?;
+        return Ok(Object::List(
+            vec![Object::Symbol("quasiquote".to_string()), inner].into(),
+        ));
+    }
+
+    if !matches!(template, Object::List(_) | Object::Pair(_)) {
+        return Ok(template.clone());
+    }
+
+    // Collect the elements and the tail: `()` for a list, the final cdr for a
+    // dotted one. `(a . ,b)` reads as `(a unquote b)`, so a trailing `unquote`
+    // is the tail too, and the only part of it to expand.
+    let mut items = Vec::new();
+    let mut rest = template;
+    let (tail, unquoted_tail) = loop {
+        match rest {
+            Object::Pair(pair) => {
+                items.push(&pair.0);
+                rest = &pair.1;
+            }
+            Object::List(list) => {
+                let n = list.len();
+                if n >= 2 && list[n - 2] == Object::Symbol("unquote".to_string()) {
+                    items.extend(list.iter().take(n - 2));
+                    break (Object::List(list.skip(n - 2)), true);
                 }
+                items.extend(list.iter());
+                break (Object::List(List::default()), false);
             }
-            params
+            _ => break (rest.clone(), false),
         }
-        _ => return Err("Invalid lambda".to_string()),
     };
 
-    let body = match &list[2] {
-        Object::List(list) => list.clone(),
-        _ => return Err("Invalid lambda".to_string()),
+    let mut result = Vec::new();
+    for item in items {
+        match special_form_arg(item, "unquote-splicing") {
+            Some(obj) if depth == 1 => match eval_obj(obj, env)? {
+                Object::List(spliced) => result.extend(spliced.iter().cloned()),
+                other => {
+                    return Err(LispError::new(ErrorKind::Type(format!(
+                        "unquote-splicing expects a list, found {}",
+                        other
+                    ))))
+                }
+            },
+            Some(obj) => {
+                let inner = quasiquote(obj, depth - 1, env)?;
+                result.push(Object::List(
+                    vec![Object::Symbol("unquote-splicing".to_string()), inner].into(),
+                ));
+            }
+            None => result.push(quasiquote(item, depth, env)?),
+        }
+    }
+    let tail = if unquoted_tail {
+        quasiquote(&tail, depth, env)?
+    } else {
+        tail
     };
-    Ok(Object::Lambda(params, body))
+    Ok(result
+        .into_iter()
+        .rev()
+        .fold(tail, |cdr, car| Object::cons(car, cdr)))
+}
+
+fn eval_function_definition(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    if list.len() < 3 {
+        return Err(LispError::new(ErrorKind::Syntax(
+            "lambda requires parameters and a body".to_string(),
+        )));
+    }
+
+    let params = parse_params(&list[1])?;
+
+    let body = list.skip(2);
+    Ok(Object::Lambda(Lambda {
+        params,
+        body,
+        env: env.clone().into(),
+    }))
 }
 
 fn eval_function_call(
-    s: &str,
-    list: &[Object],
+    func: Object,
+    list: &List,
     env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-    let lambda = env.borrow_mut().get(s);
-    if lambda.is_none() {
-        return Err(format!("Unbound symbol: {}", s));
+) -> Result<Step, LispError> {
+    let mut args = Vec::with_capacity(list.len() - 1);
+    for obj in list.iter().skip(1) {
+        args.push(eval_obj(obj, env)?);
     }
+    apply(func, &list[0].to_string(), args)
+}
 
-    let func = lambda.unwrap();
+/// Calls a procedure with its evaluated arguments. `name` is how the call
+/// refers to it, for error messages.
+fn apply(func: Object, name: &str, args: Vec<Object>) -> Result<Step, LispError> {
     match func {
-        Object::Lambda(params, body) => {
-            let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
-            for (i, param) in params.iter().enumerate() {
-                let val = eval_obj(&list[i + 1], env)?;
-                new_env.borrow_mut().set(param, val);
+        Object::Lambda(lambda) => apply_lambda(&lambda, name, args),
+        Object::Builtin(builtin) => apply_builtin(builtin, args).map(Step::Value),
+        _ => Err(LispError::new(ErrorKind::Type(format!(
+            "Not a procedure: {}",
+            name
+        )))),
+    }
+}
+
+fn apply_builtin(name: &str, args: Vec<Object>) -> Result<Object, LispError> {
+    match name {
+        "cons" | "car" | "cdr" | "list" | "null?" | "pair?" | "length" | "append" | "reverse"
+        | "list-ref" => eval_list_op(name, args),
+        "not" => {
+            check_arity(name, &args, 1, Some(1))?;
+            Ok(Object::Bool(!args[0].is_truthy()))
+        }
+        "error" => {
+            let parts: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Err(LispError::new(ErrorKind::User(parts.join(" "))))
+        }
+        _ => eval_numeric_op(name, args),
+    }
+}
+
+/// Reads a lambda list such as `(a &optional (b 1) &rest more)`, `(a . more)`
+/// or a lone symbol, which receives every argument as a list.
+fn parse_params(obj: &Object) -> Result<Params, LispError> {
+    let invalid = |msg: String| Err(LispError::new(ErrorKind::Syntax(msg)));
+
+    // A dotted tail, or a bare symbol, is the rest parameter.
+    let mut items = Vec::new();
+    let mut tail = obj;
+    loop {
+        match tail {
+            Object::List(list) => {
+                items.extend(list.iter().cloned());
+                break;
+            }
+            Object::Pair(pair) => {
+                items.push(pair.0.clone());
+                tail = &pair.1;
             }
-            return eval_obj(&Object::List(body), &mut new_env);
+            Object::Symbol(_) => {
+                items.push(Object::Symbol("&rest".to_string()));
+                items.push(tail.clone());
+                break;
+            }
+            _ => return invalid(format!("Invalid lambda parameter list {}", obj)),
+        }
+    }
+
+    #[derive(PartialEq, PartialOrd)]
+    enum Section {
+        Required,
+        Optional,
+        Rest,
+        Key,
+    }
+    let mut section = Section::Required;
+    let mut params = Params::default();
+    let mut names: Vec<String> = Vec::new();
+    for item in &items {
+        let (name, default) = match item {
+            Object::Symbol(s) if matches!(s.as_str(), "&optional" | "&rest" | "&key") => {
+                let next = match s.as_str() {
+                    "&optional" => Section::Optional,
+                    "&rest" => Section::Rest,
+                    _ => Section::Key,
+                };
+                if next <= section || (section == Section::Rest && params.rest.is_none()) {
+                    return invalid(format!("Misplaced {} in lambda parameter list", s));
+                }
+                section = next;
+                continue;
+            }
+            Object::Symbol(s) => (s.clone(), Object::Bool(false)),
+            Object::List(pair)
+                if pair.len() == 2
+                    && matches!(section, Section::Optional | Section::Key)
+                    && matches!(pair[0], Object::Symbol(_)) =>
+            {
+                (pair[0].to_string(), pair[1].clone())
+            }
+            _ => return invalid(format!("Invalid lambda parameter {}", item)),
+        };
+        if names.contains(&name) {
+            return invalid(format!("Duplicate lambda parameter {}", name));
+        }
+        names.push(name.clone());
+        match section {
+            Section::Required => params.required.push(name),
+            Section::Optional => params.optional.push((name, default)),
+            Section::Rest if params.rest.is_none() => params.rest = Some(name),
+            Section::Rest => return invalid("&rest takes a single parameter".to_string()),
+            Section::Key => params.keys.push((name, default)),
+        }
+    }
+    if section == Section::Rest && params.rest.is_none() {
+        return invalid("&rest requires a parameter name".to_string());
+    }
+    Ok(params)
+}
+
+fn apply_lambda(lambda: &Lambda, name: &str, args: Vec<Object>) -> Result<Step, LispError> {
+    let params = &lambda.params;
+    let variadic = params.rest.is_some() || !params.keys.is_empty();
+    let max = params.required.len() + params.optional.len();
+    check_arity(
+        name,
+        &args,
+        params.required.len(),
+        (!variadic).then_some(max),
+    )?;
+    if TRACE.get() {
+        let call: Vec<String> = std::iter::once(name.to_string())
+            .chain(args.iter().map(|a| Written(a).to_string()))
+            .collect();
+        eprintln!("trace: ({})", call.join(" "));
+    }
+
+    // Defaults are evaluated in the new scope, so they can refer to the
+    // parameters before them.
+    let mut new_env = Rc::new(RefCell::new(Env::extend(lambda.env.get())));
+    let mut args = args.into_iter();
+    for param in &params.required {
+        let val = args.next().unwrap();
+        new_env.borrow_mut().set(param, val);
+    }
+    for (param, default) in &params.optional {
+        let val = match args.next() {
+            Some(val) => val,
+            None => eval_obj(default, &mut new_env)?,
+        };
+        new_env.borrow_mut().set(param, val);
+    }
+    let rest: Vec<Object> = args.collect();
+    if !params.keys.is_empty() {
+        bind_keywords(params, name, &rest, &mut new_env)?;
+    }
+    if let Some(param) = &params.rest {
+        new_env.borrow_mut().set(param, Object::List(rest.into()));
+    }
+    eval_body(&lambda.body, &mut new_env)
+}
+
+/// Binds the `:name value` pairs in `args` to the keyword parameters. The
+/// first occurrence of a keyword wins; missing ones take their default.
+fn bind_keywords(
+    params: &Params,
+    name: &str,
+    args: &[Object],
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<(), LispError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(LispError::new(ErrorKind::Type(format!(
+            "Keyword arguments to {} must be :name value pairs",
+            name
+        ))));
+    }
+    for pair in args.chunks(2) {
+        let known = match &pair[0] {
+            Object::Symbol(s) => s
+                .strip_prefix(':')
+                .is_some_and(|key| params.keys.iter().any(|(k, _)| k == key)),
+            _ => false,
+        };
+        if !known {
+            return Err(LispError::new(ErrorKind::Type(format!(
+                "Unknown keyword argument {} for {}",
+                pair[0], name
+            ))));
+        }
+    }
+    for (key, default) in &params.keys {
+        let keyword = Object::Symbol(format!(":{}", key));
+        let val = match args.chunks(2).find(|pair| pair[0] == keyword) {
+            Some(pair) => pair[1].clone(),
+            None => eval_obj(default, env)?,
+        };
+        env.borrow_mut().set(key, val);
+    }
+    Ok(())
+}
+
+/// Evaluates every form of a body but the last, which is handed back in tail
+/// position.
+fn eval_body(body: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let mut forms = body.iter();
+    let mut last = match forms.next() {
+        Some(obj) => obj,
+        None => return Ok(Step::Value(Object::Void)),
+    };
+    for obj in forms {
+        eval_obj(last, env)?;
+        last = obj;
+    }
+    Ok(tail_call(last, env))
+}
+
+fn parse_bindings<'a>(name: &str, obj: &'a Object) -> Result<Vec<(String, &'a Object)>, LispError> {
+    let invalid = || {
+        LispError::new(ErrorKind::Syntax(format!(
+            "Invalid {} binding: {}",
+            name, obj
+        )))
+    };
+    let list = match obj {
+        Object::List(list) => list,
+        _ => return Err(invalid()),
+    };
+    let mut bindings = Vec::with_capacity(list.len());
+    for binding in list {
+        match binding {
+            Object::List(pair) if pair.len() == 2 => match &pair[0] {
+                Object::Symbol(s) if bindings.iter().any(|(var, _)| var == s) => {
+                    return Err(LispError::new(ErrorKind::Syntax(format!(
+                        "Duplicate {} variable {}",
+                        name, s
+                    ))))
+                }
+                Object::Symbol(s) => bindings.push((s.clone(), &pair[1])),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(bindings)
+}
+
+/// Evaluates `let`, `let*`, `letrec` and named `let`. Each creates a child
+/// environment for its bindings and leaves the enclosing one untouched:
+/// `let` evaluates the initial values in the enclosing environment, `let*`
+/// and `letrec` in the new one, so `let*` sees earlier bindings and `letrec`
+/// sees all of them.
+fn eval_let(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let name = list[0].to_string();
+    let name = name.as_str();
+    if let (true, Some(Object::Symbol(loop_name))) = (name == "let", list.get(1)) {
+        return eval_named_let(loop_name, list, env);
+    }
+    if list.len() < 3 {
+        return Err(LispError::new(ErrorKind::Syntax(format!(
+            "{} requires bindings and a body",
+            name
+        ))));
+    }
+
+    let bindings = parse_bindings(name, &list[1])?;
+    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    if name == "letrec" {
+        for (var, _) in &bindings {
+            new_env.borrow_mut().set(var, Object::Void);
         }
-        _ => return Err(format!("Not a lambda: {}", s)),
     }
+    for (var, init) in &bindings {
+        let val = if name == "let" {
+            eval_obj(init, env)?
+        } else {
+            eval_obj(init, &mut new_env)?
+        };
+        new_env.borrow_mut().set(var, val);
+    }
+    eval_body(&list.skip(2), &mut new_env)
+}
+
+/// `(let name ((var init) ...) body ...)` binds `name` to a procedure over
+/// the variables, visible only inside the body, and calls it with the
+/// initial values.
+fn eval_named_let(
+    loop_name: &str,
+    list: &List,
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Step, LispError> {
+    if list.len() < 4 {
+        return Err(LispError::new(ErrorKind::Syntax(
+            "named let requires a name, bindings and a body".to_string(),
+        )));
+    }
+
+    let bindings = parse_bindings("let", &list[2])?;
+    let mut args = Vec::with_capacity(bindings.len());
+    for (_, init) in &bindings {
+        args.push(eval_obj(init, env)?);
+    }
+
+    let loop_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    let lambda = Lambda {
+        params: Params::required(bindings.into_iter().map(|(var, _)| var).collect()),
+        body: list.skip(3),
+        env: loop_env.clone().into(),
+    };
+    loop_env
+        .borrow_mut()
+        .set(loop_name, Object::Lambda(lambda.clone()));
+    apply_lambda(&lambda, loop_name, args)
 }
 
-fn eval_symbol(s: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
+/// Looks up a variable. Keywords, symbols such as `:size` that name keyword
+/// arguments, evaluate to themselves.
+fn eval_symbol(s: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    if s.len() > 1 && s.starts_with(':') {
+        return Ok(Object::Symbol(s.to_string()));
+    }
     let val = env.borrow().get(s);
     if val.is_none() {
-        return Err(format!("Unbound symbol: {}", s));
+        return Err(LispError::new(ErrorKind::UnboundSymbol(s.to_string())));
     }
     Ok(val.unwrap().clone())
 }
 
-fn eval_list(list: &Vec<Object>, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let head = &list[0];
-    match head {
-        Object::Symbol(s) => match s.as_str() {
-            "+" | "-" | "*" | "/" | "<" | ">" | "=" | "!=" => {
-                return eval_binary_op(list, env);
+/// The special forms, which `eval_list` dispatches on by name.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "define",
+    "set!",
+    "if",
+    "when",
+    "unless",
+    "cond",
+    "case",
+    "and",
+    "or",
+    "begin",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "lambda",
+    "let",
+    "let*",
+    "letrec",
+];
+
+/// The builtin procedures, which `Env::new` binds in the global environment.
+pub const BUILTINS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "<",
+    ">",
+    "=",
+    "!=",
+    "<=",
+    ">=",
+    "quotient",
+    "rem",
+    "mod",
+    "abs",
+    "min",
+    "max",
+    "exact->inexact",
+    "inexact->exact",
+    "cons",
+    "car",
+    "cdr",
+    "list",
+    "null?",
+    "pair?",
+    "length",
+    "append",
+    "reverse",
+    "list-ref",
+    "not",
+    "error",
+];
+
+fn eval_list(list: &List, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let head = match list.first() {
+        Some(head) => head,
+        None => return Ok(Step::Value(Object::List(List::default()))),
+    };
+    if let Object::Symbol(s) = head {
+        match s.as_str() {
+            "define" => return eval_define(list, env).map(Step::Value),
+            "set!" => return eval_set(list, env).map(Step::Value),
+            "if" => return eval_if(list, env),
+            "when" | "unless" => return eval_when(list, env),
+            "cond" => return eval_cond(list, env),
+            "case" => return eval_case(list, env),
+            "and" | "or" => return eval_and_or(list, env),
+            "begin" => return eval_body(&list.tail(), env),
+            "quote" => return eval_quote(list).map(Step::Value),
+            "quasiquote" => return eval_quasiquote(list, env).map(Step::Value),
+            "unquote" | "unquote-splicing" => {
+                return Err(LispError::new(ErrorKind::Syntax(format!(
+                    "{} outside of quasiquote",
+                    s
+                ))))
             }
-            "define" => eval_define(list, env),
-            "if" => eval_if(list, env),
-            "lambda" => eval_function_definition(list),
-            _ => eval_function_call(s, list, env),
-        },
-        _ => {
-            let mut new_list = Vec::new();
-            for obj in list {
-                let result = eval_obj(obj, env)?;
-                match result {
-                    Object::Void => {}
-                    _ => new_list.push(result),
-                }
+            "lambda" => return eval_function_definition(list, env).map(Step::Value),
+            "let" | "let*" | "letrec" => return eval_let(list, env),
+            _ => {}
+        }
+    }
+
+    let func = eval_obj(head, env)?;
+    eval_function_call(func, list, env)
+}
+
+/// Counts one level of `eval_obj` nesting for as long as it is alive.
+struct DepthGuard;
+
+impl DepthGuard {
+    fn enter() -> Result<Self, LispError> {
+        let depth = DEPTH.get();
+        if depth >= MAX_DEPTH.get() {
+            return Err(LispError::new(ErrorKind::RecursionDepth));
+        }
+        DEPTH.set(depth + 1);
+        Ok(DepthGuard)
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.set(DEPTH.get() - 1);
+    }
+}
+
+fn eval_obj(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let _depth = DepthGuard::enter().map_err(|e| e.in_form(obj.id()))?;
+    let mut current_obj = obj.clone();
+    let mut current_id = obj.id();
+    let mut current_env = env.clone();
+    loop {
+        let step = match &current_obj {
+            Object::List(list) => {
+                eval_list(list, &mut current_env).map_err(|e| e.in_form(current_id))?
+            }
+            Object::Symbol(s) => {
+                return eval_symbol(s, &mut current_env).map_err(|e| e.in_form(current_id))
+            }
+            Object::String(str) => return Ok(Object::String(str.clone())),
+            Object::Integer(n) => return Ok(Object::Integer(*n)),
+            Object::Float(n) => return Ok(Object::Float(*n)),
+            Object::BigInteger(_) | Object::Rational(_) => return Ok(current_obj),
+            Object::Pair(_) => {
+                return Err(LispError::new(ErrorKind::Syntax(format!(
+                    "Cannot evaluate dotted list {}",
+                    current_obj
+                )))
+                .in_form(current_id))
+            }
+            Object::Lambda(_) | Object::Builtin(_) => return Ok(current_obj),
+            Object::Bool(_) => return Ok(current_obj),
+            Object::Void => return Ok(Object::Void),
+        };
+        match step {
+            Step::Value(val) => return Ok(val),
+            Step::TailCall(next_obj, next_id, next_env) => {
+                current_obj = next_obj;
+                current_id = next_id;
+                current_env = next_env;
             }
-            Ok(Object::List(new_list))
         }
     }
 }
 
-fn eval_obj(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    match obj {
-        Object::List(list) => eval_list(list, env),
-        Object::Symbol(s) => eval_symbol(s, env),
-        Object::String(str) => Ok(Object::String(str.clone())),
-        Object::Integer(n) => Ok(Object::Integer(*n)),
-        Object::Float(n) => Ok(Object::Float(*n)),
-        Object::Lambda(_params, _body) => Ok(Object::Void),
-        Object::Bool(_) => Ok(obj.clone()),
-        Object::Void => Ok(Object::Void),
+/// Evaluates each top-level form of `program` in order and returns the value
+/// of the last one, or `Void` when there are none.
+pub fn eval(program: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    eval_forms(program, env, |_, _| {})
+}
+
+/// Like `eval`, but calls `evaluated` with each top-level form and its span
+/// as soon as that form has been evaluated successfully.
+pub fn eval_forms(
+    program: &str,
+    env: &mut Rc<RefCell<Env>>,
+    mut evaluated: impl FnMut(&Object, Span),
+) -> Result<Object, LispError> {
+    let (forms, spans) = parse_with_spans(program)?;
+    let mut result = Object::Void;
+    for form in &forms {
+        result = eval_obj(form, env).map_err(|e| locate_error(e, &spans))?;
+        evaluated(form, spans[&form.id()]);
     }
+    Ok(result)
 }
 
-pub fn eval(program: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let parsed_list = parse(program);
-    if parsed_list.is_err() {
-        return Err(format!("{}", parsed_list.err().unwrap()));
+/// Gives an evaluation error the span of the innermost form it happened in
+/// that is part of the program. Forms from elsewhere, such as the body of a
+/// procedure defined by earlier input, have no span here.
+fn locate_error(err: LispError, spans: &SpanTable) -> LispError {
+    if err.span.is_some() {
+        return err;
+    }
+    match err.forms.iter().find_map(|id| spans.get(id)) {
+        Some(&span) => err.with_span(span),
+        None => err,
     }
-    eval_obj(&parsed_list.unwrap(), env)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
 
     #[test]
     fn test_simple_add() {
@@ -223,75 +1135,63 @@ mod tests {
     #[test]
     fn test_area_of_a_circle() {
         let mut env = Rc::new(RefCell::new(Env::new()));
-        let program = "(
+        let program = "
           (define r 10)
           (define pi 314)
           (* pi (* r r))
-        )";
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::List(vec![Object::Integer(314 * 10 * 10)]))
+        assert_eq!(result, Object::Integer(314 * 10 * 10))
     }
 
     #[test]
     fn test_sqr_function() {
         let mut env = Rc::new(RefCell::new(Env::new()));
-        let program = "(
+        let program = "
                         (define sqr (lambda (r) (* r r)))
                         (sqr 10)
-                       )";
+                       ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::List(vec![Object::Integer((10 * 10) as i64)])
-        );
+        assert_eq!(result, Object::Integer((10 * 10) as i64));
     }
 
     #[test]
     fn test_fibonaci() {
         let mut env = Rc::new(RefCell::new(Env::new()));
         let program = "
-            (
-                (define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))
-                (fib 10)
-            )
+            (define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))
+            (fib 10)
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::List(vec![Object::Integer(89_i64)]));
+        assert_eq!(result, Object::Integer(89_i64));
     }
 
     #[test]
     fn test_factorial() {
         let mut env = Rc::new(RefCell::new(Env::new()));
         let program = "
-            (
-                (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
-                (fact 5)
-            )
+            (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+            (fact 5)
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::List(vec![Object::Integer(120_i64)]));
+        assert_eq!(result, Object::Integer(120_i64));
     }
 
     #[test]
     fn test_circle_area_function() {
         let mut env = Rc::new(RefCell::new(Env::new()));
         let program = "
-            (
-                (define pi 314)
-                (define r 10)
-                (define sqr (lambda (r) (* r r)))
-                (define area (lambda (r) (* pi (sqr r))))
-                (area r)
-            )
+            (define pi 314)
+            (define r 10)
+            (define sqr (lambda (r) (* r r)))
+            (define area (lambda (r) (* pi (sqr r))))
+            (area r)
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::List(vec![Object::Integer((314 * 10 * 10) as i64)])
-        );
+        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
     }
 
     // 浮動小数点数の計算
@@ -315,4 +1215,1020 @@ mod tests {
         let result = eval(program, &mut env).unwrap();
         assert_eq!(result, Object::String("hello world".to_string()));
     }
+
+    #[test]
+    fn test_closure_captures_defining_env() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define make-adder (lambda (n) (lambda (x) (+ x n))))
+            (define add5 (make-adder 5))
+            (define n 100)
+            (add5 10)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(15));
+    }
+
+    #[test]
+    fn test_lexical_scope() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define x 1)
+            (define get-x (lambda () (+ x 0)))
+            (define shadow (lambda (x) (get-x)))
+            (shadow 2)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(1));
+    }
+
+    #[test]
+    fn test_anonymous_function_application() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let result = eval("((lambda (x) (* x x)) 5)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(25));
+    }
+
+    #[test]
+    fn test_computed_head_application() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "((if (< 1 2) (lambda (x) (+ x 1)) (lambda (x) (- x 1))) 10)";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(11));
+    }
+
+    #[test]
+    fn test_higher_order_function() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define apply-twice (lambda (f x) (f (f x))))
+            (apply-twice (lambda (x) (* x 2)) 3)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(12));
+    }
+
+    #[test]
+    fn test_tail_call_runs_in_constant_stack() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define loop (lambda (n) (if (= n 0) 0 (loop (- n 1)))))
+            (loop 100000)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(0));
+    }
+
+    #[test]
+    fn test_tail_recursive_accumulator() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define sum (lambda (n acc) (if (= n 0) acc (sum (- n 1) (+ acc n)))))
+            (sum 100000 0)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(5000050000));
+    }
+
+    #[test]
+    fn test_recursion_depth_limit() {
+        let run = |n: i64| {
+            let mut env = Rc::new(RefCell::new(Env::new()));
+            let program = "(define f (lambda (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))";
+            eval(program, &mut env).unwrap();
+            assert_eq!(
+                eval(&format!("(f {})", n), &mut env),
+                Ok(Object::Integer(n))
+            );
+
+            let err = eval("(f 100000)", &mut env).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::RecursionDepth);
+            // The depth is unwound after the error.
+            assert_eq!(
+                eval(&format!("(f {})", n), &mut env),
+                Ok(Object::Integer(n))
+            );
+        };
+        // The test thread has the default stack and limit.
+        run(20);
+        // Like the binary, with a larger stack the limit rises to suit it.
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                set_stack_size(STACK_SIZE);
+                run(700)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unbound_symbol_error() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(+ 1 x)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("x".to_string()));
+    }
+
+    #[test]
+    fn test_arity_error() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define sqr (lambda (r) (* r r)))
+            (sqr 1 2)
+        ";
+
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Arity {
+                name: "sqr".to_string(),
+                expected: "1".to_string(),
+                found: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_type_error() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(+ 1 \"2\")", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+    }
+
+    #[test]
+    fn test_user_error() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(error \"bad value:\" (+ 1 2))", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::User("bad value: 3".to_string()));
+    }
+
+    #[test]
+    fn test_error_span() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define f (lambda (x) (+ x 1)))
+            (f \"one\")
+        ";
+        let err = eval(program, &mut env).unwrap_err();
+        let span = err.span.unwrap();
+        assert_eq!(&program[span.start..span.end], "(+ x 1)");
+        assert_eq!((span.line, span.column), (2, 35));
+
+        let err = eval("(+ 1 undefined)", &mut env).unwrap_err();
+        let span = err.span.unwrap();
+        assert_eq!((span.start, span.end), (5, 14));
+    }
+
+    #[test]
+    fn test_error_span_of_repeated_form() {
+        // Spans belong to the occurrence that failed, not the first one that
+        // looks the same.
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "(define x 5) (if #f (car x) 0) (car x)";
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(err.span.unwrap().column, 32);
+
+        let program = "(define f (lambda (w) w)) (+ 1 w)";
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("w".to_string()));
+        assert_eq!(err.span.unwrap().column, 32);
+
+        // Tail calls and `let` bindings keep track of the occurrence too.
+        let program = "(define g (lambda (y) (if y (car y) (car y)))) (g #f)";
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(err.span.unwrap().column, 37);
+        let program = "(let ((a z) (b (car 1))) (car 1))";
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(err.span.unwrap().column, 10);
+        let program = "(let ((a 1) (b (car 1))) (car 1))";
+        let err = eval(program, &mut env).unwrap_err();
+        assert_eq!(err.span.unwrap().column, 16);
+    }
+
+    #[test]
+    fn test_variadic_arithmetic() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(+)", Object::Integer(0)),
+            ("(*)", Object::Integer(1)),
+            ("(+ 1 2 3)", Object::Integer(6)),
+            ("(* 1 2 3 4)", Object::Integer(24)),
+            ("(- 5)", Object::Integer(-5)),
+            ("(- 10 1 2 3)", Object::Integer(4)),
+            ("(/ 2.0)", Object::Float(0.5)),
+            ("(/ 100 5 2)", Object::Integer(10)),
+            ("(+ 1 2.5 3)", Object::Float(6.5)),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(< 1 2 3)", true),
+            ("(< 1 3 2)", false),
+            ("(<= 1 1 2)", true),
+            ("(>= 3 3 1)", true),
+            ("(> 3 2 2)", false),
+            ("(= 2 2 2.0)", true),
+            ("(!= 1 2)", true),
+            ("(< 1)", true),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap(),
+                Object::Bool(expected),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer_division_ops() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(quotient 17 5)", 3),
+            ("(quotient -17 5)", -3),
+            ("(rem 17 5)", 2),
+            ("(rem -17 5)", -2),
+            ("(mod 17 5)", 2),
+            ("(mod -17 5)", 3),
+            ("(mod 17 -5)", -3),
+            ("(abs -7)", 7),
+            ("(min 3 1 2)", 1),
+            ("(max 3 1 2)", 3),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap(),
+                Object::Integer(expected),
+                "{}",
+                program
+            );
+        }
+        assert_eq!(eval("(max 1 2.0 3)", &mut env).unwrap(), Object::Float(3.0));
+    }
+
+    #[test]
+    fn test_numeric_op_arity() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(-)", &mut env).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Arity {
+                name: "-".to_string(),
+                expected: "at least 1".to_string(),
+                found: 0,
+            }
+        );
+        let err = eval("(mod 1)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Arity { .. }));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        for program in [
+            "(/ 1 0)",
+            "(/ 1.5 0)",
+            "(/ 0)",
+            "(quotient 1 0)",
+            "(rem 1 0)",
+            "(mod 1 0)",
+        ] {
+            let err = eval(program, &mut env).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::DivisionByZero, "{}", program);
+        }
+        assert_eq!(
+            eval("(/ 1 0.0)", &mut env).unwrap(),
+            Object::Float(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow_promotes() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let max = i64::MAX;
+        let min = i64::MIN;
+        let two_pow_63 = "9223372036854775808".parse::<BigInt>().unwrap();
+        let cases = [
+            (format!("(+ {} 1)", max), two_pow_63.clone()),
+            (format!("(- {} 1)", min), -two_pow_63.clone() - 1),
+            (format!("(- {})", min), two_pow_63.clone()),
+            (format!("(* {} 2)", max), BigInt::from(max) * 2),
+            (format!("(/ {} -1)", min), two_pow_63.clone()),
+            (format!("(quotient {} -1)", min), two_pow_63.clone()),
+            (format!("(abs {})", min), two_pow_63.clone()),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(&program, &mut env).unwrap(),
+                Object::BigInteger(expected),
+                "{}",
+                program
+            );
+        }
+        assert_eq!(
+            eval(&format!("(mod {} -1)", min), &mut env).unwrap(),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            eval(&format!("(- (+ {} 1) 1)", max), &mut env).unwrap(),
+            Object::Integer(max)
+        );
+    }
+
+    #[test]
+    fn test_bignum_factorial() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+            (fact 25)
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::BigInteger("15511210043330985984000000".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_rationals() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let ratio = |n: i64, d: i64| Object::Rational(BigRational::new(n.into(), d.into()));
+        let cases = [
+            ("(/ 1 3)", ratio(1, 3)),
+            ("(/ 6 3)", Object::Integer(2)),
+            ("(+ 1/3 1/6)", ratio(1, 2)),
+            ("(* 2/3 3/2)", Object::Integer(1)),
+            ("(- 1/2)", ratio(-1, 2)),
+            ("(/ 4)", ratio(1, 4)),
+            ("(abs -3/4)", ratio(3, 4)),
+            ("(max 1/2 1/3)", ratio(1, 2)),
+            ("(+ 1/2 0.5)", Object::Float(1.0)),
+            ("(exact->inexact 1/4)", Object::Float(0.25)),
+            ("(inexact->exact 0.25)", ratio(1, 4)),
+            ("(inexact->exact 3.0)", Object::Integer(3)),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+        assert_eq!(
+            eval("(< 1/3 0.34 1/2)", &mut env).unwrap(),
+            Object::Bool(true)
+        );
+        assert_eq!(eval("(= 1/2 0.5)", &mut env).unwrap(), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_quote() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define xs '(1 2 3))
+            (list xs (quote (a b)) 'x '())
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(
+                vec![
+                    Object::List(
+                        vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)].into()
+                    ),
+                    Object::List(
+                        vec![
+                            Object::Symbol("a".to_string()),
+                            Object::Symbol("b".to_string())
+                        ]
+                        .into()
+                    ),
+                    Object::Symbol("x".to_string()),
+                    Object::List(vec![].into()),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        eval("(define xs '(2 3))", &mut env).unwrap();
+        eval("(define f (lambda () (+ 1 2)))", &mut env).unwrap();
+        eval("(define x 5)", &mut env).unwrap();
+        let cases = [
+            ("`(1 ,(+ 1 1) 3)", "(1 2 3)"),
+            ("`(1 . ,x)", "(1 . 5)"),
+            ("`(1 . ,xs)", "(1 2 3)"),
+            ("`(1 ,@'(2) . 3)", "(1 2 . 3)"),
+            ("`((a . ,(+ 1 1)) (b . c))", "((a . 2) (b . c))"),
+            ("`(1 `(2 . ,(3 ,x)))", "(1 (quasiquote (2 unquote (3 5))))"),
+            ("`(0 ,@xs 4)", "(0 2 3 4)"),
+            ("`(a (b ,(f)))", "(a (b 3))"),
+            ("`x", "x"),
+            (
+                "`(1 `(2 ,(3 ,(+ 1 3))))",
+                "(1 (quasiquote (2 (unquote (3 4)))))",
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap().to_string(),
+                expected,
+                "{}",
+                program
+            );
+        }
+
+        let err = eval("`(1 ,@2)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(unquote x)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Syntax(_)));
+    }
+
+    #[test]
+    fn test_list_primitives() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        eval("(define xs (list 1 2 3))", &mut env).unwrap();
+        let cases = [
+            ("(cdr (cons 0 xs))", "(1 2 3)"),
+            ("(cons 0 xs)", "(0 1 2 3)"),
+            ("(cons 1 2)", "(1 . 2)"),
+            ("(cons 1 (cons 2 3))", "(1 2 . 3)"),
+            ("(car xs)", "1"),
+            ("(cdr xs)", "(2 3)"),
+            ("(cdr (cdr (cdr xs)))", "()"),
+            ("(cdr '(a . b))", "b"),
+            ("(car '((1 2) 3))", "(1 2)"),
+            ("(length xs)", "3"),
+            ("(length '())", "0"),
+            ("(reverse xs)", "(3 2 1)"),
+            ("(append xs '(4) '() '(5 6))", "(1 2 3 4 5 6)"),
+            ("(append)", "()"),
+            ("(append '(1) 2)", "(1 . 2)"),
+            ("(list-ref xs 2)", "3"),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap().to_string(),
+                expected,
+                "{}",
+                program
+            );
+        }
+        assert_eq!(
+            eval("(cons 1 '(2))", &mut env).unwrap(),
+            eval("(list 1 2)", &mut env).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_list_predicates() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(null? '())", true),
+            ("(null? '(1))", false),
+            ("(null? 0)", false),
+            ("(pair? '(1))", true),
+            ("(pair? (cons 1 2))", true),
+            ("(pair? '())", false),
+            ("(pair? 1)", false),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap(),
+                Object::Bool(expected),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_errors() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(car '())", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(car (cdr '(a . b)))", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(length (cons 1 2))", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(list-ref '(1 2) 2)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Range(_)));
+    }
+
+    #[test]
+    fn test_cdr_shares_storage() {
+        let list: List = (0..1000).map(Object::Integer).collect();
+        let mut tail = list.clone();
+        for _ in 0..999 {
+            tail = tail.tail();
+        }
+        assert_eq!(tail, List::from(vec![Object::Integer(999)]));
+        assert!(std::ptr::eq(&list[999], &tail[0]));
+    }
+
+    #[test]
+    fn test_cons_shares_storage() {
+        let list: List = (0..1000).map(Object::Integer).collect();
+        let longer = List::cons(Object::Integer(-1), list.clone());
+        assert_eq!(longer.len(), 1001);
+        assert!(std::ptr::eq(&list[0], &longer[1]));
+
+        // Each cons is constant time, so building a long list by consing onto
+        // an accumulator is linear.
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define build (lambda (n acc) (if (= n 0) acc (build (- n 1) (cons n acc)))))
+            (define xs (build 100000 '()))
+            (list (length xs) (car xs) (car (cdr xs)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(100000 1 2)");
+    }
+
+    #[test]
+    fn test_let() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define x 10)
+            (list
+              (let ((x 1) (y x)) (+ x y))
+              (let* ((x 1) (y (+ x 1))) (* x y))
+              (letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                       (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                (even? 100))
+              (let () 1 2 3)
+              x)
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(
+                vec![
+                    Object::Integer(11),
+                    Object::Integer(2),
+                    Object::Bool(true),
+                    Object::Integer(3),
+                    Object::Integer(10),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_let_does_not_leak_bindings() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        eval("(let ((tmp 1)) (+ tmp 1))", &mut env).unwrap();
+        let err = eval("(+ tmp 1)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("tmp".to_string()));
+    }
+
+    #[test]
+    fn test_named_let() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (let loop ((i 0) (acc 0))
+              (if (= i 100000) acc (loop (+ i 1) (+ acc i))))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(4999950000));
+
+        let err = eval("(loop 1 2)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("loop".to_string()));
+    }
+
+    #[test]
+    fn test_recursive_bindings_are_freed() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define count (lambda (n) (let loop ((i 0)) (if (= i n) i (loop (+ i 1))))))
+            (define down (lambda (n)
+              (letrec ((f (lambda (k) (if (= k 0) 'done (f (- k 1)))))) (f n))))
+        ";
+        eval(program, &mut env).unwrap();
+        let before = Rc::strong_count(&env);
+        for _ in 0..10 {
+            assert_eq!(eval("(count 3)", &mut env), Ok(Object::Integer(3)));
+            eval("(down 3)", &mut env).unwrap();
+        }
+        // Each call's scope, whose parent is the global one, has been freed.
+        assert_eq!(Rc::strong_count(&env), before);
+
+        // A procedure that escapes its scope keeps it alive.
+        let program = "
+            (define again (let loop ((i 0)) loop))
+            (define even (letrec ((ev (lambda (n) (if (= n 0) #t (od (- n 1)))))
+                                  (od (lambda (n) (if (= n 0) #f (ev (- n 1))))))
+                           ev))
+            (even 11)
+        ";
+        assert_eq!(eval(program, &mut env), Ok(Object::Bool(false)));
+        let result = eval("((again 1) 2)", &mut env).unwrap();
+        assert!(matches!(result, Object::Lambda(_)));
+    }
+
+    #[test]
+    fn test_invalid_let() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        for program in [
+            "(let ((x)) x)",
+            "(let (x 1) x)",
+            "(let ((1 2)) 1)",
+            "(let ())",
+            "(let ((x 1) (x 2)) x)",
+            "(let* ((x 1) (y 2) (x 3)) x)",
+            "(letrec ((f 1) (f 2)) f)",
+            "(let loop ((i 0) (i 1)) i)",
+        ] {
+            let err = eval(program, &mut env).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Syntax(_)), "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_set_updates_enclosing_scope() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define make-counter
+              (lambda ()
+                (let ((count 0))
+                  (lambda () (let () (set! count (+ count 1)) count)))))
+            (define c1 (make-counter))
+            (define c2 (make-counter))
+            (define total 0)
+            (define add! (lambda (n) (set! total (+ total n))))
+            (list (c1) (c1) (c2) (begin (add! 5) (add! 7) total))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(
+                vec![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::Integer(1),
+                    Object::Integer(12),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_set_unbound_symbol() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let err = eval("(set! undefined 1)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("undefined".to_string()));
+        let err = eval("(let ((x 1)) (set! y x))", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("y".to_string()));
+        assert_eq!(env.borrow().get("y"), None);
+    }
+
+    #[test]
+    fn test_begin() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "(begin (define x 1) (set! x (+ x 1)) (* x 10))";
+        assert_eq!(eval(program, &mut env).unwrap(), Object::Integer(20));
+        assert_eq!(eval("(begin)", &mut env).unwrap(), Object::Void);
+    }
+
+    #[test]
+    fn test_when_unless() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(when (< 1 2) 1 2)", Object::Integer(2)),
+            ("(when (> 1 2) 1 2)", Object::Void),
+            ("(unless (> 1 2) 1 2)", Object::Integer(2)),
+            ("(unless (< 1 2) 1 2)", Object::Void),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_multi_expression_body() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define log '())
+            (define f (lambda (x)
+                (set! log (cons x log))
+                (define y (* x 2))
+                y))
+            (list (f 1) (f 2) log)
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(2 4 (2 1))");
+
+        let program = "(define id (lambda (x) x))";
+        eval(program, &mut env).unwrap();
+        assert_eq!(eval("(id 5)", &mut env).unwrap(), Object::Integer(5));
+        let err = eval("(lambda (x))", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Syntax(_)));
+    }
+
+    #[test]
+    fn test_tail_call_through_begin_and_when() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define count 0)
+            (define loop (lambda (n)
+                (set! count (+ count 1))
+                (when (> n 0) (begin (loop (- n 1))))))
+            (loop 100000)
+            count
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(100001));
+    }
+
+    #[test]
+    fn test_truthiness() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(if 1 2 3)", 2),
+            ("(if 0 2 3)", 2),
+            ("(if '() 2 3)", 2),
+            ("(if \"\" 2 3)", 2),
+            ("(if (< 2 1) 2 3)", 3),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap(),
+                Object::Integer(expected),
+                "{}",
+                program
+            );
+        }
+        assert_eq!(eval("(if #t 1)", &mut env), Ok(Object::Integer(1)));
+        assert_eq!(eval("(if #f 1)", &mut env), Ok(Object::Void));
+        let err = eval("(if #t)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Arity { .. }));
+    }
+
+    #[test]
+    fn test_cond() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        eval(
+            "(define sign (lambda (n) (cond ((< n 0) 'negative) ((= n 0) 'zero) (else 'positive))))",
+            &mut env,
+        )
+        .unwrap();
+        for (n, expected) in [(-5, "negative"), (0, "zero"), (3, "positive")] {
+            let result = eval(&format!("(sign {})", n), &mut env).unwrap();
+            assert_eq!(result, Object::Symbol(expected.to_string()));
+        }
+
+        let cases = [
+            ("(cond ((< 2 1) 1))", Object::Void),
+            ("(cond ((+ 1 2)))", Object::Integer(3)),
+            (
+                "(cond ((car '(5 6)) => (lambda (x) (* x x))) (else 0))",
+                Object::Integer(25),
+            ),
+            (
+                "(cond ((< 2 1) 1) ((< 1 2) (define z 1) (+ z 1)))",
+                Object::Integer(2),
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+
+        let err = eval("(cond (else 1) ((< 1 2) 2))", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Syntax(_)));
+    }
+
+    #[test]
+    fn test_case() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        eval(
+            "(define kind (lambda (x) (case x ((1 2 3) 'small) ((a b) 'letter) (else 'other))))",
+            &mut env,
+        )
+        .unwrap();
+        for (x, expected) in [("2", "small"), ("'b", "letter"), ("42", "other")] {
+            let result = eval(&format!("(kind {})", x), &mut env).unwrap();
+            assert_eq!(result, Object::Symbol(expected.to_string()), "{}", x);
+        }
+        assert_eq!(eval("(case 9 ((1) 1))", &mut env).unwrap(), Object::Void);
+    }
+
+    #[test]
+    fn test_and_or_not() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(and)", Object::Bool(true)),
+            ("(or)", Object::Bool(false)),
+            ("(and 1 2 3)", Object::Integer(3)),
+            ("(and 1 (< 2 1) 3)", Object::Bool(false)),
+            ("(or (< 2 1) 7 8)", Object::Integer(7)),
+            ("(or (< 2 1) (< 3 1))", Object::Bool(false)),
+            ("(not (< 2 1))", Object::Bool(true)),
+            ("(not 0)", Object::Bool(false)),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+
+        // Short-circuiting never evaluates the unbound symbol.
+        assert_eq!(
+            eval("(and (< 2 1) undefined)", &mut env).unwrap(),
+            Object::Bool(false)
+        );
+        assert_eq!(
+            eval("(or 1 undefined)", &mut env).unwrap(),
+            Object::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_bool_and_nil_literals() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let cases = [
+            ("(if #t 1 2)", Object::Integer(1)),
+            ("(if #f 1 2)", Object::Integer(2)),
+            ("(if false 1 2)", Object::Integer(2)),
+            ("(not true)", Object::Bool(false)),
+            ("(null? nil)", Object::Bool(true)),
+            ("(null? '())", Object::Bool(true)),
+            (
+                "(cons 1 nil)",
+                Object::List(vec![Object::Integer(1)].into()),
+            ),
+            (
+                "(list #t #f)",
+                Object::List(vec![Object::Bool(true), Object::Bool(false)].into()),
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(eval(program, &mut env).unwrap(), expected, "{}", program);
+        }
+        assert_eq!(
+            eval("(list #t #f nil)", &mut env).unwrap().to_string(),
+            "(#t #f ())"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            ; Sums the squares of a list.
+            (begin
+              #| a block comment
+                 #| which nests |# |#
+              (define sum-squares
+                (lambda (xs)
+                  (if (null? xs)
+                      0 ; the base case
+                      (+ (* (car xs) (car xs)) (sum-squares (cdr xs))))))
+              (sum-squares '(1 2 #;100 3)))
+        ";
+        assert_eq!(eval(program, &mut env).unwrap(), Object::Integer(14));
+    }
+
+    #[test]
+    fn test_top_level_forms() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        assert_eq!(eval("42", &mut env).unwrap(), Object::Integer(42));
+        assert_eq!(eval("", &mut env).unwrap(), Object::Void);
+        assert_eq!(
+            eval("(define x 1) x \"s\" (+ x 1)", &mut env).unwrap(),
+            Object::Integer(2)
+        );
+        assert_eq!(eval("x", &mut env).unwrap(), Object::Integer(1));
+
+        // A list whose head is not a procedure is an error, not a sequence.
+        let err = eval("(1 2 3)", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(define y 2) (+ y z)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnboundSymbol("z".to_string()));
+        assert_eq!(err.span.map(|s| (s.start, s.end)), Some((18, 19)));
+        assert_eq!(eval("y", &mut env).unwrap(), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_special_forms_and_builtins_are_known() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        for form in SPECIAL_FORMS {
+            if let Err(err) = eval(&format!("({})", form), &mut env) {
+                assert_ne!(err.kind, ErrorKind::UnboundSymbol(form.to_string()));
+            }
+        }
+        for name in BUILTINS {
+            assert_eq!(eval(name, &mut env), Ok(Object::Builtin(name)));
+        }
+    }
+
+    #[test]
+    fn test_builtins_are_first_class() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define plus +)
+            (define map (lambda (f xs)
+              (if (null? xs) '() (cons (f (car xs)) (map f (cdr xs))))))
+            (list (plus 1 2)
+                  (map car '((1 2) (3 4)))
+                  (cond ('(5 6) => car))
+                  ((lambda (list) (list 1 2)) (lambda (a b) (+ a b)))
+                  (let ((car cdr)) (car '(7 8))))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(3 (1 3) 5 3 (8))");
+        // Shadowing a builtin locally leaves the global binding alone.
+        assert_eq!(eval("(car '(7 8))", &mut env), Ok(Object::Integer(7)));
+
+        let err = eval("(plus 1 \"a\")", &mut env).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Type(_)));
+        let err = eval("(1 2)", &mut env).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Type("Not a procedure: 1".to_string()));
+    }
+
+    #[test]
+    fn test_lambda_parameters() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let program = "
+            (define tail (lambda (a . rest) rest))
+            (define all (lambda args args))
+            (define tail2 (lambda (a &rest rest) (cons a rest)))
+            (define range (lambda (end &optional (start 0) (step (if (< start end) 1 -1)))
+              (list start end step)))
+            (define box (lambda (name &key (width 1) (height width)) (list name width height)))
+            (define mixed (lambda (a &rest more &key (x 0)) (list a more x)))
+        ";
+        eval(program, &mut env).unwrap();
+        let cases = [
+            ("(tail 1)", "()"),
+            ("(tail 1 2 3)", "(2 3)"),
+            ("(all)", "()"),
+            ("(all 1 2)", "(1 2)"),
+            ("(tail2 1 2 3)", "(1 2 3)"),
+            ("(range 5)", "(0 5 1)"),
+            ("(range 5 10)", "(10 5 -1)"),
+            ("(range 5 0 2)", "(0 5 2)"),
+            ("(box 'a)", "(a 1 1)"),
+            ("(box 'a :height 3)", "(a 1 3)"),
+            ("(box 'a :width 2)", "(a 2 2)"),
+            ("(box 'a :height 3 :width 2 :height 4)", "(a 2 3)"),
+            ("(mixed 1 :x 5)", "(1 (:x 5) 5)"),
+            ("(list :key)", "(:key)"),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(
+                eval(program, &mut env).unwrap().to_string(),
+                expected,
+                "{}",
+                program
+            );
+        }
+
+        let arity = |program: &str, env: &mut Rc<RefCell<Env>>| match eval(program, env) {
+            Err(LispError {
+                kind: ErrorKind::Arity {
+                    expected, found, ..
+                },
+                ..
+            }) => (expected, found),
+            other => panic!("{}: {:?}", program, other),
+        };
+        assert_eq!(arity("(tail)", &mut env), ("at least 1".to_string(), 0));
+        assert_eq!(arity("(range)", &mut env), ("1 to 3".to_string(), 0));
+        assert_eq!(
+            arity("(range 1 2 3 4)", &mut env),
+            ("1 to 3".to_string(), 4)
+        );
+        for program in ["(box 'a :depth 1)", "(box 'a :width)", "(box 'a 1 2)"] {
+            let err = eval(program, &mut env).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Type(_)), "{}", program);
+        }
+        for program in [
+            "(lambda (a a) a)",
+            "(lambda (&rest) 1)",
+            "(lambda (&rest a b) 1)",
+            "(lambda (&key a &optional b) 1)",
+            "(lambda ((a 1)) a)",
+            "(lambda (a . 1) a)",
+        ] {
+            let err = eval(program, &mut env).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Syntax(_)), "{}", program);
+        }
+        assert_eq!(
+            eval("range", &mut env).unwrap().to_string(),
+            "Lambda(end &optional (start 0) (step (if (< start end) 1 -1)) )(list start end step) "
+        );
+    }
 }
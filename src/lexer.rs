@@ -1,103 +1,337 @@
+use crate::error::{ErrorKind, LispError, Span};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Integer(i64),
+    BigInteger(BigInt),
+    Rational(BigRational),
     Float(f64),
+    Bool(bool),
+    Nil,
     String(String),
     Symbol(String),
     LParen,
     RParen,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+    /// `#;`, which comments out the datum that follows it.
+    DatumComment,
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Token::Integer(n) => write!(f, "{}", n),
+            Token::BigInteger(n) => write!(f, "{}", n),
+            Token::Rational(n) => write!(f, "{}", n),
             Token::Float(n) => write!(f, "{}", n),
+            Token::Bool(true) => write!(f, "#t"),
+            Token::Bool(false) => write!(f, "#f"),
+            Token::Nil => write!(f, "nil"),
             Token::String(s) => write!(f, "{}", s),
             Token::Symbol(s) => write!(f, "{}", s),
             Token::LParen => write!(f, "("),
             Token::RParen => write!(f, ")"),
+            Token::Quote => write!(f, "'"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
+            Token::UnquoteSplicing => write!(f, ",@"),
+            Token::DatumComment => write!(f, "#;"),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct TokenError {
-    err: String,
+/// A token together with the span of source text it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
 }
 
-impl fmt::Display for TokenError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Tokenization error: {}", self.err)
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, LispError> {
+    let mut lexer = Lexer {
+        input,
+        offset: 0,
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
     }
+    Ok(tokens)
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, TokenError> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().collect::<Vec<char>>();
+struct Lexer<'a> {
+    input: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
 
-    if chars.is_empty() {
-        return Ok(tokens);
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
     }
 
-    while !chars.is_empty() {
-        let mut ch = chars.remove(0);
-        match ch {
-            '(' => tokens.push(Token::LParen),
-            ')' => tokens.push(Token::RParen),
-            '"' => {
-                let mut word = String::new();
-                while !chars.is_empty() && chars[0] != '"' {
-                    word.push(chars.remove(0));
+    fn advance(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    /// Reads the escape sequence after a backslash in a string literal. A
+    /// backslash at the end of a line continues the string on the next one,
+    /// skipping the line break and the next line's indentation.
+    fn escape(&mut self, word: &mut String) -> Result<(), LispError> {
+        let (start, line, column) = (self.offset - 1, self.line, self.column - 1);
+        let invalid = |lexer: &Self, msg: String| {
+            LispError::new(ErrorKind::Lex(msg)).with_span(Span {
+                start,
+                end: lexer.offset,
+                line,
+                column,
+            })
+        };
+
+        match self.advance() {
+            Some('"') => word.push('"'),
+            Some('\\') => word.push('\\'),
+            Some('n') => word.push('\n'),
+            Some('t') => word.push('\t'),
+            Some('r') => word.push('\r'),
+            Some('0') => word.push('\0'),
+            Some('\n') => {
+                while self.peek().is_some_and(|c| c == ' ' || c == '\t') {
+                    self.advance();
+                }
+            }
+            Some('u') => {
+                if self.advance() != Some('{') {
+                    return Err(invalid(self, "Expected '{' after \\u".to_string()));
+                }
+                let mut digits = String::new();
+                loop {
+                    match self.advance() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+                        _ => {
+                            return Err(invalid(
+                                self,
+                                "Invalid unicode escape, expected \\u{XXXX}".to_string(),
+                            ))
+                        }
+                    }
                 }
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => word.push(c),
+                    None => {
+                        return Err(invalid(
+                            self,
+                            format!("Invalid unicode code point: \\u{{{}}}", digits),
+                        ))
+                    }
+                }
+            }
+            Some(c) => return Err(invalid(self, format!("Invalid escape sequence: \\{}", c))),
+            // Left for the caller to report as an unterminated string.
+            None => {}
+        }
+        Ok(())
+    }
 
-                if !chars.is_empty() && chars[0] == '"' {
-                    chars.remove(0);
-                } else {
-                    return Err(TokenError {
-                        err: format!("Unterminated string: {}", word),
-                    });
+    /// Skips whitespace, `;` line comments and `#| ... |#` block comments,
+    /// which may nest.
+    fn skip_atmosphere(&mut self) -> Result<(), LispError> {
+        loop {
+            let rest = &self.input[self.offset..];
+            if rest.starts_with(char::is_whitespace) {
+                self.advance();
+            } else if rest.starts_with(';') {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
                 }
+            } else if rest.starts_with("#|") {
+                self.block_comment()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
 
-                tokens.push(Token::String(word));
+    fn block_comment(&mut self) -> Result<(), LispError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        self.advance();
+        self.advance();
+        let mut depth = 1;
+        while depth > 0 {
+            let rest = &self.input[self.offset..];
+            if rest.starts_with("|#") {
+                depth -= 1;
+            } else if rest.starts_with("#|") {
+                depth += 1;
+            } else if self.advance().is_some() {
+                continue;
+            } else {
+                return Err(LispError::new(ErrorKind::Incomplete(
+                    "Unterminated block comment".to_string(),
+                ))
+                .with_span(Span {
+                    start,
+                    end: start + 2,
+                    line,
+                    column,
+                }));
             }
-            _ => {
+            self.advance();
+            self.advance();
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Result<Option<SpannedToken>, LispError> {
+        self.skip_atmosphere()?;
+
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let span = |end| Span {
+            start,
+            end,
+            line,
+            column,
+        };
+
+        let ch = match self.advance() {
+            Some(ch) => ch,
+            None => return Ok(None),
+        };
+        let token = match ch {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '\'' => Token::Quote,
+            '`' => Token::Quasiquote,
+            ',' if self.peek() == Some('@') => {
+                self.advance();
+                Token::UnquoteSplicing
+            }
+            ',' => Token::Unquote,
+            '#' if self.peek() == Some(';') => {
+                self.advance();
+                Token::DatumComment
+            }
+            '"' => {
                 let mut word = String::new();
-                while !chars.is_empty() && !ch.is_whitespace() && ch != '(' && ch != ')' {
-                    word.push(ch);
-                    let peek = chars[0];
-                    if peek == '(' || peek == ')' {
-                        break;
+                while let Some(ch) = self.advance() {
+                    match ch {
+                        '"' => {
+                            return Ok(Some(SpannedToken {
+                                token: Token::String(word),
+                                span: span(self.offset),
+                            }))
+                        }
+                        '\\' => self.escape(&mut word)?,
+                        _ => word.push(ch),
                     }
+                }
 
-                    ch = chars.remove(0);
+                return Err(LispError::new(ErrorKind::Incomplete(format!(
+                    "Unterminated string: {}",
+                    word
+                )))
+                .with_span(span(self.offset)));
+            }
+            _ => {
+                let mut word = ch.to_string();
+                while let Some(ch) = self.peek() {
+                    if ch.is_whitespace() || ch == '(' || ch == ')' || ch == ';' {
+                        break;
+                    }
+                    word.push(ch);
+                    self.advance();
                 }
 
-                if !word.is_empty() {
-                    tokens.push(if let Ok(i) = word.parse::<i64>() {
-                        Token::Integer(i)
-                    } else if let Ok(f) = word.parse::<f64>() {
-                        Token::Float(f)
-                    } else {
-                        Token::Symbol(word)
-                    });
+                if let Some(token) = literal(&word) {
+                    token
+                } else if let Ok(i) = word.parse::<i64>() {
+                    Token::Integer(i)
+                } else if let Ok(n) = word.parse::<BigInt>() {
+                    Token::BigInteger(n)
+                } else if let Some(n) = parse_rational(&word) {
+                    if n.denom().is_zero() {
+                        return Err(LispError::new(ErrorKind::Lex(format!(
+                            "Zero denominator in rational: {}",
+                            word
+                        )))
+                        .with_span(span(self.offset)));
+                    }
+                    Token::Rational(BigRational::new(n.numer().clone(), n.denom().clone()))
+                } else if let Ok(f) = word.parse::<f64>() {
+                    Token::Float(f)
+                } else {
+                    Token::Symbol(word)
                 }
             }
-        }
+        };
+
+        Ok(Some(SpannedToken {
+            token,
+            span: span(self.offset),
+        }))
     }
+}
 
-    Ok(tokens)
+/// Recognises the boolean and empty-list literals.
+fn literal(word: &str) -> Option<Token> {
+    match word {
+        "#t" | "#true" | "true" => Some(Token::Bool(true)),
+        "#f" | "#false" | "false" => Some(Token::Bool(false)),
+        "nil" => Some(Token::Nil),
+        _ => None,
+    }
+}
+
+/// Splits a literal such as `-1/3` into its numerator and denominator
+/// without reducing it, so a zero denominator can still be reported.
+fn parse_rational(word: &str) -> Option<BigRational> {
+    let (numer, denom) = word.split_once('/')?;
+    if !denom.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(BigRational::new_raw(
+        numer.parse().ok()?,
+        denom.parse().ok()?,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenize(input)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
     #[test]
     fn test_add() {
-        let tokens = tokenize("(+ 1 2)").unwrap_or_default();
+        let tokens = tokens("(+ 1 2)");
         assert_eq!(
             tokens,
             vec![
@@ -124,7 +358,7 @@ mod tests {
             std::f64::consts::PI
         );
 
-        let tokens = tokenize(program).unwrap_or_default();
+        let tokens = tokens(program);
         assert_eq!(
             tokens,
             vec![
@@ -157,4 +391,158 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn test_unterminated_string() {
+        let err = tokenize("(define s\n  \"hello)").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Incomplete("Unterminated string: hello)".to_string())
+        );
+        assert_eq!(
+            err.span,
+            Some(Span::locate("(define s\n  \"hello)", 12, 19))
+        );
+        assert_eq!(err.span.unwrap().line, 2);
+        assert_eq!(err.span.unwrap().column, 3);
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let tokens = tokenize("(define s\n  \"日本\")").unwrap();
+        let spans = tokens
+            .iter()
+            .map(|t| (t.span.start, t.span.end, t.span.line, t.span.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 1, 1, 1),
+                (1, 7, 1, 2),
+                (8, 9, 1, 9),
+                (12, 20, 2, 3),
+                (20, 21, 2, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trailing_atom() {
+        assert_eq!(tokens("42"), vec![Token::Integer(42)]);
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        assert_eq!(
+            tokens("12345678901234567890 1/3 -4/6 1/ /"),
+            vec![
+                Token::BigInteger("12345678901234567890".parse().unwrap()),
+                Token::Rational(BigRational::new(1.into(), 3.into())),
+                Token::Rational(BigRational::new((-2).into(), 3.into())),
+                Token::Symbol("1/".to_string()),
+                Token::Symbol("/".to_string()),
+            ]
+        );
+        let err = tokenize("(+ 1/0)").unwrap_err();
+        assert_eq!(err.span.map(|s| (s.start, s.end)), Some((3, 6)));
+    }
+
+    #[test]
+    fn test_quote_tokens() {
+        assert_eq!(
+            tokens("'x `(a ,b ,@c)"),
+            vec![
+                Token::Quote,
+                Token::Symbol("x".to_string()),
+                Token::Quasiquote,
+                Token::LParen,
+                Token::Symbol("a".to_string()),
+                Token::Unquote,
+                Token::Symbol("b".to_string()),
+                Token::UnquoteSplicing,
+                Token::Symbol("c".to_string()),
+                Token::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bool_and_nil_literals() {
+        assert_eq!(
+            tokens("#t #f true false #true #false nil nil? #tx"),
+            vec![
+                Token::Bool(true),
+                Token::Bool(false),
+                Token::Bool(true),
+                Token::Bool(false),
+                Token::Bool(true),
+                Token::Bool(false),
+                Token::Nil,
+                Token::Symbol("nil?".to_string()),
+                Token::Symbol("#tx".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            tokens(r#""a\"b\\c\nd\te\u{3042}\u{1F600}""#),
+            vec![Token::String("a\"b\\c\nd\te\u{3042}\u{1F600}".to_string())]
+        );
+        assert_eq!(
+            tokens("\"first\nsecond \\\n    third\""),
+            vec![Token::String("first\nsecond third".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        let cases = [
+            (r#"(f "ab\q")"#, (6, 8)),
+            (r#""\u{110000}""#, (1, 11)),
+            (r#""\u{zz}""#, (1, 5)),
+        ];
+        for (input, expected) in cases {
+            let err = tokenize(input).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Lex(_)), "{}", input);
+            assert_eq!(
+                err.span.map(|s| (s.start, s.end)),
+                Some(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            tokens("(a ; the rest of the line\n b;c\n #| outer #| inner |# |# d)"),
+            vec![
+                Token::LParen,
+                Token::Symbol("a".to_string()),
+                Token::Symbol("b".to_string()),
+                Token::Symbol("d".to_string()),
+                Token::RParen,
+            ]
+        );
+        assert_eq!(
+            tokens("(a #;(b c))"),
+            vec![
+                Token::LParen,
+                Token::Symbol("a".to_string()),
+                Token::DatumComment,
+                Token::LParen,
+                Token::Symbol("b".to_string()),
+                Token::Symbol("c".to_string()),
+                Token::RParen,
+                Token::RParen,
+            ]
+        );
+
+        let err = tokenize("(a #| #| |#)").unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(err.span.map(|s| (s.start, s.end)), Some((3, 5)));
+    }
 }
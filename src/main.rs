@@ -1,42 +1,304 @@
 mod env;
+mod error;
 mod eval;
 mod lexer;
+mod number;
 mod object;
 mod parser;
+mod session;
 
-use linefeed::{Interface, ReadResult};
+use linefeed::{Completer, Completion, Interface, Prompter, ReadResult, Signal, Terminal};
 use object::Object;
 use std::cell::RefCell;
+use std::io::{IsTerminal, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::rc::Rc;
+use std::sync::Arc;
 
 const PROMPT: &str = ">> ";
+const CONTINUATION_PROMPT: &str = ".. ";
+const HISTORY_FILE: &str = ".lisp-rs_history";
+const ERROR_COLOR: &str = "\x1b[31m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+const USAGE: &str = "usage: lisp-rs [script.lisp | -e expr | -] [args...]";
+
+fn main() -> ExitCode {
+    // Non-tail recursion in Lisp recurses in the evaluator, so it runs on a
+    // thread with a larger stack than usual when one can be had, and with the
+    // lower depth limit of an ordinary stack otherwise.
+    let spawned = std::thread::Builder::new()
+        .stack_size(eval::STACK_SIZE)
+        .spawn(|| {
+            eval::set_stack_size(eval::STACK_SIZE);
+            run()
+        });
+    match spawned {
+        Ok(handle) => handle.join().unwrap_or(ExitCode::FAILURE),
+        Err(_) => run(),
+    }
+}
+
+fn run() -> ExitCode {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| "lisp-rs".to_string());
+    let args: Vec<String> = args.collect();
+
+    // Each mode yields the name `command-line` reports first, the source to
+    // run and the remaining arguments for the script.
+    let (name, source, script_args) = match args.first().map(String::as_str) {
+        None if std::io::stdin().is_terminal() => {
+            return match repl(&program) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("lisp-rs: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        None => ("-", read_stdin(), &args[..]),
+        Some("-") => ("-", read_stdin(), &args[1..]),
+        Some("-e") => match args.get(1) {
+            Some(expr) => ("-e", Ok(expr.clone()), &args[2..]),
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        },
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Some(path) => (path, std::fs::read_to_string(path), &args[1..]),
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("lisp-rs: cannot read {}: {}", name, e);
+            return ExitCode::from(2);
+        }
+    };
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let reader = Interface::new(PROMPT).unwrap();
     let mut env = Rc::new(RefCell::new(env::Env::new()));
+    session::define_command_line(&env, name, script_args);
+    match eval::eval(&source, &mut env) {
+        Ok(val) => {
+            print_value(val, false);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e.render(&source));
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    reader.set_prompt(PROMPT.as_ref()).unwrap();
+fn read_stdin() -> std::io::Result<String> {
+    let mut source = String::new();
+    std::io::stdin().read_to_string(&mut source)?;
+    Ok(source)
+}
+
+fn repl(program: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = Interface::new("lisp-rs")?;
+    reader.set_prompt(PROMPT)?;
+    reader.set_report_signal(Signal::Interrupt, true);
+    {
+        let mut lock = reader.lock_reader();
+        lock.set_blink_matching_paren(true);
+        // Unlike the shell-oriented default, `<`, `=` and friends are part of
+        // symbols rather than word breaks.
+        lock.set_word_break_chars(" \t\n\"'`,;()");
+    }
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing or unreadable history file just means starting afresh.
+        let _ = reader.load_history(path);
+    }
+    let color = std::env::var_os("NO_COLOR").is_none();
+    let (color_out, color_err) = (
+        color && std::io::stdout().is_terminal(),
+        color && std::io::stderr().is_terminal(),
+    );
+    let mut session = session::Session::new(program);
 
-    while let ReadResult::Input(input) = reader.read_line()? {
-        if input.eq("exit") {
+    // Lines are collected in `pending` until they form complete input.
+    let mut pending = String::new();
+    loop {
+        reader.set_completer(Arc::new(SymbolCompleter::new(&session.env.borrow())));
+        let line = match reader.read_line()? {
+            ReadResult::Input(line) => line,
+            ReadResult::Signal(Signal::Interrupt) => {
+                // Ctrl-C abandons whatever has been entered so far.
+                println!("^C");
+                pending.clear();
+                reader.set_prompt(PROMPT)?;
+                continue;
+            }
+            _ => break,
+        };
+        if pending.is_empty() && line == "exit" {
             break;
         }
-        let val = eval::eval(input.as_ref(), &mut env)?;
-        match val {
-            Object::Void => {}
-            Object::Lambda(params, body) => {
-                println!("Lambda(");
-                for param in params {
-                    println!("  {}", param);
-                }
-                println!(")");
-                for expr in body {
-                    println!(" {}", expr);
-                }
+        if !line.trim().is_empty() {
+            reader.add_history_unique(line.clone());
+        }
+        if pending.is_empty() && line.trim_start().starts_with(',') {
+            match catch_panic(|| session.run_command(&line)) {
+                Ok(Ok(text)) => println!("{}", text),
+                Ok(Err(msg)) => print_error(&msg, color_err),
+                Err(msg) => print_error(&format!("Internal error: {}", msg), color_err),
             }
-            _ => println!("{}", val),
+            continue;
+        }
+        if !pending.is_empty() {
+            pending.push('\n');
+        }
+        pending.push_str(&line);
+        if parser::parse(&pending).is_err_and(|e| e.is_incomplete()) {
+            reader.set_prompt(CONTINUATION_PROMPT)?;
+            continue;
+        }
+
+        let input = std::mem::take(&mut pending);
+        reader.set_prompt(PROMPT)?;
+        match catch_panic(|| session.eval(&input)) {
+            Ok(Ok(val)) => print_value(val, color_out),
+            Ok(Err(e)) => print_error(&e.render(&input), color_err),
+            Err(msg) => print_error(&format!("Internal error: {}", msg), color_err),
         }
     }
 
+    if let Some(path) = &history {
+        if let Err(e) = reader.save_history(path) {
+            eprintln!("lisp-rs: cannot save history to {}: {}", path.display(), e);
+        }
+    }
     Ok(())
 }
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Completes special forms along with every symbol, builtins included, bound
+/// when the completer was created.
+struct SymbolCompleter {
+    names: Vec<String>,
+}
+
+impl SymbolCompleter {
+    fn new(env: &env::Env) -> Self {
+        let mut names = env.names();
+        names.extend(eval::SPECIAL_FORMS.iter().map(|k| k.to_string()));
+        names.sort();
+        names.dedup();
+        SymbolCompleter { names }
+    }
+}
+
+impl<Term: Terminal> Completer<Term> for SymbolCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        _prompter: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        Some(
+            self.names
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .map(|name| Completion::simple(name.clone()))
+                .collect(),
+        )
+    }
+}
+
+/// Runs `f`, returning the message of any panic it raises so that one bad
+/// expression cannot end the session.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    // The caller reports the panic, so the hook that would print it is
+    // silenced while `f` runs, and only then.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.map_err(|payload| {
+        if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "unknown panic".to_string()
+        }
+    })
+}
+
+fn print_error(msg: &str, color: bool) {
+    if color {
+        eprintln!("{}{}{}", ERROR_COLOR, msg, RESET_COLOR);
+    } else {
+        eprintln!("{}", msg);
+    }
+}
+
+fn print_value(val: Object, color: bool) {
+    match val {
+        Object::Void => {}
+        Object::Lambda(lambda) => {
+            println!("Lambda(");
+            for param in lambda.params.items() {
+                println!("  {}", param);
+            }
+            println!(")");
+            for expr in &lambda.body {
+                println!(" {}", expr);
+            }
+        }
+        _ if color => println!("{}", object::Highlighted(&val)),
+        _ => println!("{}", object::Written(&val)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        let mut env = Rc::new(RefCell::new(env::Env::new()));
+        let result = catch_panic(|| eval::eval("(define x 1) (+ x y)", &mut env));
+        assert!(matches!(result, Ok(Err(_))));
+        assert_eq!(
+            catch_panic(|| eval::eval("x", &mut env)),
+            Ok(Ok(Object::Integer(1)))
+        );
+
+        let result = catch_panic(|| -> i32 { panic!("evaluator bug {}", 42) });
+        assert_eq!(result, Err("evaluator bug 42".to_string()));
+        assert_eq!(
+            catch_panic(|| eval::eval("(+ x 1)", &mut env)),
+            Ok(Ok(Object::Integer(2)))
+        );
+    }
+
+    #[test]
+    fn test_symbol_completer() {
+        let mut env = Rc::new(RefCell::new(env::Env::new()));
+        eval::eval("(define letter 1) (define lambda-count 2)", &mut env).unwrap();
+        let completer = SymbolCompleter::new(&env.borrow());
+        let starting_with = |prefix: &str| -> Vec<&str> {
+            completer
+                .names
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(String::as_str)
+                .collect()
+        };
+        assert_eq!(starting_with("let"), ["let", "let*", "letrec", "letter"]);
+        assert_eq!(starting_with("lambda"), ["lambda", "lambda-count"]);
+    }
+}
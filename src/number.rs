@@ -0,0 +1,185 @@
+use crate::error::*;
+use crate::object::Object;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+// The numeric tower, from narrowest to widest: `Integer` (i64), `BigInteger`,
+// `Rational` and `Float`. Exact results are always narrowed back down, so a
+// `BigInteger` never fits in an i64 and a `Rational` never has denominator 1.
+// Any `Float` operand makes the result inexact.
+
+pub fn is_number(obj: &Object) -> bool {
+    matches!(
+        obj,
+        Object::Integer(_) | Object::BigInteger(_) | Object::Rational(_) | Object::Float(_)
+    )
+}
+
+pub fn from_bigint(n: BigInt) -> Object {
+    match n.to_i64() {
+        Some(n) => Object::Integer(n),
+        None => Object::BigInteger(n),
+    }
+}
+
+pub fn from_rational(n: BigRational) -> Object {
+    if n.is_integer() {
+        from_bigint(n.to_integer())
+    } else {
+        Object::Rational(n)
+    }
+}
+
+fn to_rational(obj: &Object) -> Option<BigRational> {
+    match obj {
+        Object::Integer(n) => Some(BigRational::from_integer(BigInt::from(*n))),
+        Object::BigInteger(n) => Some(BigRational::from_integer(n.clone())),
+        Object::Rational(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+fn to_bigint(obj: &Object) -> Option<BigInt> {
+    match obj {
+        Object::Integer(n) => Some(BigInt::from(*n)),
+        Object::BigInteger(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+fn to_f64(obj: &Object) -> Option<f64> {
+    match obj {
+        Object::Integer(n) => Some(*n as f64),
+        Object::BigInteger(n) => n.to_f64(),
+        Object::Rational(n) => n.to_f64(),
+        Object::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn is_exact_zero(obj: &Object) -> bool {
+    matches!(obj, Object::Integer(0))
+}
+
+fn type_error(op: &str, left: &Object, right: &Object) -> LispError {
+    LispError::new(ErrorKind::Type(format!(
+        "Invalid types for {} operator {} {}",
+        op, left, right
+    )))
+}
+
+/// Applies `+`, `-`, `*` or `/` to two numbers.
+pub fn arithmetic(op: &str, left: &Object, right: &Object) -> Result<Object, LispError> {
+    if !is_number(left) || !is_number(right) {
+        return Err(type_error(op, left, right));
+    }
+    if op == "/" && is_exact_zero(right) {
+        return Err(LispError::new(ErrorKind::DivisionByZero));
+    }
+
+    if let (Object::Integer(l), Object::Integer(r)) = (left, right) {
+        let result = match op {
+            "+" => l.checked_add(*r),
+            "-" => l.checked_sub(*r),
+            "*" => l.checked_mul(*r),
+            _ if l.checked_rem(*r) == Some(0) => l.checked_div(*r),
+            _ => None,
+        };
+        if let Some(n) = result {
+            return Ok(Object::Integer(n));
+        }
+    }
+
+    if let (Some(l), Some(r)) = (to_rational(left), to_rational(right)) {
+        return Ok(from_rational(match op {
+            "+" => l + r,
+            "-" => l - r,
+            "*" => l * r,
+            _ => l / r,
+        }));
+    }
+
+    let (l, r) = (to_f64(left).unwrap(), to_f64(right).unwrap());
+    Ok(Object::Float(match op {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        _ => l / r,
+    }))
+}
+
+/// Applies `quotient`, `rem` or `mod` to two integers. `quotient` and `rem`
+/// truncate towards zero; the result of `mod` takes the sign of the divisor.
+pub fn integer_division(op: &str, left: &Object, right: &Object) -> Result<Object, LispError> {
+    let (l, r) = match (to_bigint(left), to_bigint(right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return Err(type_error(op, left, right)),
+    };
+    if r.is_zero() {
+        return Err(LispError::new(ErrorKind::DivisionByZero));
+    }
+    Ok(from_bigint(match op {
+        "quotient" => l / r,
+        "rem" => l % r,
+        _ => l.mod_floor(&r),
+    }))
+}
+
+/// Orders two numbers, or returns `None` when either is NaN.
+pub fn compare(op: &str, left: &Object, right: &Object) -> Result<Option<Ordering>, LispError> {
+    if let (Object::Integer(l), Object::Integer(r)) = (left, right) {
+        return Ok(Some(l.cmp(r)));
+    }
+    if let (Some(l), Some(r)) = (to_rational(left), to_rational(right)) {
+        return Ok(Some(l.cmp(&r)));
+    }
+    match (to_f64(left), to_f64(right)) {
+        (Some(l), Some(r)) => Ok(l.partial_cmp(&r)),
+        _ => Err(type_error(op, left, right)),
+    }
+}
+
+pub fn abs(obj: &Object) -> Result<Object, LispError> {
+    match obj {
+        Object::Integer(n) => Ok(n
+            .checked_abs()
+            .map_or_else(|| from_bigint(BigInt::from(*n).abs()), Object::Integer)),
+        Object::BigInteger(n) => Ok(from_bigint(n.abs())),
+        Object::Rational(n) => Ok(from_rational(n.abs())),
+        Object::Float(n) => Ok(Object::Float(n.abs())),
+        _ => Err(LispError::new(ErrorKind::Type(format!(
+            "Invalid type for abs {}",
+            obj
+        )))),
+    }
+}
+
+pub fn exact_to_inexact(obj: &Object) -> Result<Object, LispError> {
+    match to_f64(obj) {
+        Some(n) => Ok(Object::Float(n)),
+        None => Err(LispError::new(ErrorKind::Type(format!(
+            "Invalid type for exact->inexact {}",
+            obj
+        )))),
+    }
+}
+
+pub fn inexact_to_exact(obj: &Object) -> Result<Object, LispError> {
+    match obj {
+        Object::Float(n) => match BigRational::from_f64(*n) {
+            Some(n) => Ok(from_rational(n)),
+            None => Err(LispError::new(ErrorKind::Type(format!(
+                "No exact representation of {}",
+                obj
+            )))),
+        },
+        _ if is_number(obj) => Ok(obj.clone()),
+        _ => Err(LispError::new(ErrorKind::Type(format!(
+            "Invalid type for inexact->exact {}",
+            obj
+        )))),
+    }
+}
@@ -1,47 +1,509 @@
+use crate::env::Env;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::cell::RefCell;
 use std::fmt;
+use std::ops::Index;
+use std::rc::{Rc, Weak};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
     Void,
     Integer(i64),
+    BigInteger(BigInt),
+    Rational(BigRational),
     Float(f64),
     Bool(bool),
     Symbol(String),
     String(String),
-    Lambda(Vec<String>, Vec<Object>),
-    List(Vec<Object>),
+    Lambda(Lambda),
+    /// A builtin procedure, named as in `eval::BUILTINS`.
+    Builtin(&'static str),
+    List(List),
+    /// A pair whose `cdr` is not a list, such as `(a . b)`. Anything that
+    /// ends in a proper list is a `List`, so each value has one representation.
+    Pair(Rc<(Object, Object)>),
+}
+
+/// A proper list. Lists are immutable chains of cons cells which share their
+/// tails, so `cons` and taking the `cdr` are constant time and never copy.
+#[derive(Clone, Default)]
+pub struct List(Option<Rc<Node>>);
+
+struct Node {
+    car: Object,
+    cdr: List,
+    /// The length of the list starting at this cell.
+    len: usize,
+}
+
+impl List {
+    pub fn cons(head: Object, tail: List) -> List {
+        let len = tail.len() + 1;
+        List(Some(Rc::new(Node {
+            car: head,
+            cdr: tail,
+            len,
+        })))
+    }
+
+    /// Everything after the first element; the empty list for an empty list.
+    pub fn tail(&self) -> List {
+        self.skip(1)
+    }
+
+    /// The list without its first `n` elements, sharing the rest.
+    pub fn skip(&self, n: usize) -> List {
+        let mut list = self;
+        for _ in 0..n {
+            match &list.0 {
+                Some(node) => list = &node.cdr,
+                None => break,
+            }
+        }
+        list.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn first(&self) -> Option<&Object> {
+        self.0.as_ref().map(|node| &node.car)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Object> {
+        self.iter().nth(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self)
+    }
+}
+
+impl Index<usize> for List {
+    type Output = Object;
+
+    fn index(&self, index: usize) -> &Object {
+        match self.get(index) {
+            Some(obj) => obj,
+            None => panic!(
+                "index {} out of range for a list of length {}",
+                index,
+                self.len()
+            ),
+        }
+    }
+}
+
+// Dropping the cells recursively would overflow the stack on a long or
+// deeply nested list, so the ones no other list shares are unlinked one at a
+// time, keeping the lists found in their cars for later.
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut nested = Vec::new();
+        let mut next = self.0.take();
+        loop {
+            while let Some(node) = next {
+                next = match Rc::try_unwrap(node) {
+                    Ok(mut node) => {
+                        if let Object::List(car) = &mut node.car {
+                            nested.extend(car.0.take());
+                        }
+                        node.cdr.0.take()
+                    }
+                    Err(_) => None,
+                };
+            }
+            match nested.pop() {
+                Some(node) => next = Some(node),
+                None => break,
+            }
+        }
+    }
+}
+
+impl From<Vec<Object>> for List {
+    fn from(items: Vec<Object>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(List::default(), |tail, item| List::cons(item, tail))
+    }
+}
+
+impl FromIterator<Object> for List {
+    fn from_iter<I: IntoIterator<Item = Object>>(iter: I) -> Self {
+        iter.into_iter().collect::<Vec<_>>().into()
+    }
+}
+
+pub struct Iter<'a>(&'a List);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Object;
+
+    fn next(&mut self) -> Option<&'a Object> {
+        let node = self.0 .0.as_ref()?;
+        self.0 = &node.cdr;
+        Some(&node.car)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Object;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Object {
+    /// Only `#f` counts as false in a condition; every other value, including
+    /// `0`, `""`, the empty list and `Void`, is true.
+    pub fn is_truthy(&self) -> bool {
+        *self != Object::Bool(false)
+    }
+
+    /// Identifies this occurrence of an object by its address. Lists never
+    /// move their elements, so this is stable for as long as the object is
+    /// part of a program, while a clone is a different occurrence.
+    pub fn id(&self) -> usize {
+        self as *const Object as usize
+    }
+
+    /// Builds the pair `(car . cdr)`, which is a list when `cdr` is one.
+    pub fn cons(car: Object, cdr: Object) -> Object {
+        match cdr {
+            Object::List(list) => Object::List(List::cons(car, list)),
+            _ => Object::Pair(Rc::new((car, cdr))),
+        }
+    }
+}
+
+/// The parameter list of a `lambda`, which in full reads
+/// `(a b &optional (c 0) &rest more &key (size 1))`. `(a b . more)` is
+/// shorthand for `(a b &rest more)`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    pub required: Vec<String>,
+    /// Each optional parameter with the expression giving its default.
+    pub optional: Vec<(String, Object)>,
+    pub rest: Option<String>,
+    /// Each keyword parameter, passed as `:name value`, with its default.
+    pub keys: Vec<(String, Object)>,
+}
+
+impl Params {
+    pub fn required(names: Vec<String>) -> Self {
+        Params {
+            required: names,
+            ..Default::default()
+        }
+    }
+
+    /// The parameter list written out as it would appear in a `lambda`.
+    pub fn items(&self) -> Vec<Object> {
+        let symbol = |s: &str| Object::Symbol(s.to_string());
+        let with_default = |(name, default): &(String, Object)| {
+            Object::List(vec![symbol(name), default.clone()].into())
+        };
+        let mut items: Vec<Object> = self.required.iter().map(|s| symbol(s)).collect();
+        if !self.optional.is_empty() {
+            items.push(symbol("&optional"));
+            items.extend(self.optional.iter().map(with_default));
+        }
+        if let Some(rest) = &self.rest {
+            items.push(symbol("&rest"));
+            items.push(symbol(rest));
+        }
+        if !self.keys.is_empty() {
+            items.push(symbol("&key"));
+            items.extend(self.keys.iter().map(with_default));
+        }
+        items
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let items: Vec<String> = self.items().iter().map(|i| i.to_string()).collect();
+        write!(f, "{}", items.join(" "))
+    }
+}
+
+/// A closure: the parameters and body of a `lambda` together with the
+/// environment it was evaluated in.
+#[derive(Clone)]
+pub struct Lambda {
+    pub params: Params,
+    pub body: List,
+    pub env: Captured,
+}
+
+impl Lambda {
+    /// This closure holding its environment strongly.
+    pub fn upgraded(&self) -> Lambda {
+        Lambda {
+            env: Captured::Strong(self.env.get()),
+            ..self.clone()
+        }
+    }
+}
+
+impl PartialEq for Lambda {
+    fn eq(&self, other: &Self) -> bool {
+        self.params == other.params
+            && self.body == other.body
+            && std::ptr::eq(self.env.as_ptr(), other.env.as_ptr())
+    }
+}
+
+/// The environment a closure captured. A closure bound in that very
+/// environment, as by `letrec` or a named `let`, refers to it weakly so that
+/// the two do not keep each other alive forever. `Env::get` only hands out
+/// strong closures, so a weak one never outlives its environment.
+#[derive(Clone)]
+pub enum Captured {
+    Strong(Rc<RefCell<Env>>),
+    Weak(Weak<RefCell<Env>>),
+}
+
+impl Captured {
+    pub fn get(&self) -> Rc<RefCell<Env>> {
+        match self {
+            Captured::Strong(env) => env.clone(),
+            Captured::Weak(env) => env
+                .upgrade()
+                .expect("a weakly captured environment outlived its closure"),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const RefCell<Env> {
+        match self {
+            Captured::Strong(env) => Rc::as_ptr(env),
+            Captured::Weak(env) => env.as_ptr(),
+        }
+    }
+}
+
+impl From<Rc<RefCell<Env>>> for Captured {
+    fn from(env: Rc<RefCell<Env>>) -> Self {
+        Captured::Strong(env)
+    }
+}
+
+// The captured environment usually contains the closure itself, so it is
+// left out to keep `Debug` from recursing forever.
+impl fmt::Debug for Lambda {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Lambda")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
 }
 
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, Style::Display, 0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Display,
+    Written,
+    Highlighted,
+}
+
+/// Formats an object the way `write` does: unlike `Display`, strings are
+/// quoted and escaped, so the output reads back as the same object.
+pub struct Written<'a>(pub &'a Object);
+
+impl fmt::Display for Written<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_with(f, Style::Written, 0)
+    }
+}
+
+/// Formats an object like `Written`, with numbers and strings coloured using
+/// ANSI escape codes for display in a terminal.
+pub struct Highlighted<'a>(pub &'a Object);
+
+impl fmt::Display for Highlighted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_with(f, Style::Highlighted, 0)
+    }
+}
+
+const NUMBER_COLOR: &str = "\x1b[33m";
+const STRING_COLOR: &str = "\x1b[32m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+impl Object {
+    /// Formats the object nested `depth` levels inside the one being printed.
+    /// Past the depth evaluation is limited to, which a list built in a loop
+    /// can exceed, the rest is elided as `...`.
+    fn fmt_with(&self, f: &mut fmt::Formatter, style: Style, depth: usize) -> fmt::Result {
+        if depth >= crate::eval::max_depth() {
+            return write!(f, "...");
+        }
+        if style == Style::Highlighted {
+            let color = match self {
+                Object::Integer(_)
+                | Object::BigInteger(_)
+                | Object::Rational(_)
+                | Object::Float(_) => Some(NUMBER_COLOR),
+                Object::String(_) => Some(STRING_COLOR),
+                _ => None,
+            };
+            if let Some(color) = color {
+                write!(f, "{}", color)?;
+                self.fmt_with(f, Style::Written, depth)?;
+                return write!(f, "{}", RESET_COLOR);
+            }
+        }
+
+        let item = |obj: &Object, f: &mut fmt::Formatter| obj.fmt_with(f, style, depth + 1);
         match self {
             Object::Void => write!(f, "Void"),
             Object::Integer(n) => write!(f, "{}", n),
-            Object::Float(n) => write!(f, "{}", n),
-            Object::Bool(b) => write!(f, "{}", b),
+            Object::BigInteger(n) => write!(f, "{}", n),
+            Object::Rational(n) => write!(f, "{}", n),
+            // `{:?}` keeps the fractional part of whole floats ("2.0"), so
+            // inexact numbers stay distinguishable from exact ones.
+            Object::Float(n) => write!(f, "{:?}", n),
+            Object::Bool(true) => write!(f, "#t"),
+            Object::Bool(false) => write!(f, "#f"),
             Object::Symbol(s) => write!(f, "{}", s),
+            Object::String(str) if style != Style::Display => write_escaped(f, str),
             Object::String(str) => write!(f, "{}", str),
-            Object::Lambda(params, body) => {
+            Object::Lambda(lambda) => {
                 write!(f, "Lambda(")?;
-                for param in params {
-                    write!(f, "{} ", param)?;
+                for param in lambda.params.items() {
+                    item(&param, f)?;
+                    write!(f, " ")?;
                 }
                 write!(f, ")")?;
-                for expr in body {
-                    write!(f, "{} ", expr)?;
+                for expr in &lambda.body {
+                    item(expr, f)?;
+                    write!(f, " ")?;
                 }
                 Ok(())
             }
+            Object::Builtin(name) => write!(f, "Builtin({})", name),
             Object::List(list) => {
                 write!(f, "(")?;
                 for (i, obj) in list.iter().enumerate() {
                     if i > 0 {
                         write!(f, " ")?;
                     }
-                    write!(f, "{}", obj)?;
+                    item(obj, f)?;
+                }
+                write!(f, ")")
+            }
+            Object::Pair(pair) => {
+                write!(f, "(")?;
+                item(&pair.0, f)?;
+                let mut rest = &pair.1;
+                while let Object::Pair(pair) = rest {
+                    write!(f, " ")?;
+                    item(&pair.0, f)?;
+                    rest = &pair.1;
                 }
+                write!(f, " . ")?;
+                item(rest, f)?;
                 write!(f, ")")
             }
         }
     }
 }
+
+fn write_escaped(f: &mut fmt::Formatter, str: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in str.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_strings() {
+        let obj = Object::List(
+            vec![
+                Object::String("say \"hi\"\n\tC:\\ \u{7}".to_string()),
+                Object::Symbol("x".to_string()),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            Written(&obj).to_string(),
+            r#"("say \"hi\"\n\tC:\\ \u{7}" x)"#
+        );
+        assert_eq!(obj.to_string(), "(say \"hi\"\n\tC:\\ \u{7} x)");
+    }
+
+    #[test]
+    fn test_highlighted() {
+        let obj = Object::List(
+            vec![
+                Object::Integer(1),
+                Object::String("a".to_string()),
+                Object::Symbol("b".to_string()),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            Highlighted(&obj).to_string(),
+            "(\x1b[33m1\x1b[0m \x1b[32m\"a\"\x1b[0m b)"
+        );
+    }
+
+    #[test]
+    fn test_deeply_nested_list() {
+        let mut obj = Object::Integer(1);
+        for _ in 0..100_000 {
+            obj = Object::List(vec![obj].into());
+        }
+        let depth = crate::eval::max_depth();
+        let elided = format!("{}...{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(obj.to_string(), elided);
+        drop(obj);
+    }
+}
@@ -1,127 +1,510 @@
+use crate::error::*;
+use crate::eval;
 use crate::lexer::*;
+use crate::number;
 use crate::object::*;
-use std::error::Error;
-use std::fmt;
+use std::collections::HashMap;
 
-#[derive(Debug)]
-pub struct ParseError {
-    err: String,
+/// A parsed form annotated with the span of source text it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub span: Span,
 }
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Parse error: {}", self.err)
-    }
+/// The span of every form in a parsed program, keyed by `Object::id`.
+pub type SpanTable = HashMap<usize, Span>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxKind {
+    Atom(Object),
+    List(Vec<SyntaxNode>),
+    /// `(a b . c)`: the elements before the dot and the tail after it.
+    DottedList(Vec<SyntaxNode>, Box<SyntaxNode>),
 }
 
-impl Error for ParseError {}
+impl SyntaxNode {
+    pub fn to_object(&self) -> Object {
+        match &self.kind {
+            SyntaxKind::Atom(obj) => obj.clone(),
+            SyntaxKind::List(nodes) => Object::List(nodes.iter().map(|n| n.to_object()).collect()),
+            SyntaxKind::DottedList(nodes, tail) => nodes
+                .iter()
+                .rev()
+                .fold(tail.to_object(), |cdr, n| Object::cons(n.to_object(), cdr)),
+        }
+    }
 
-pub fn parse(program: &str) -> Result<Object, ParseError> {
-    let token_result = tokenize(program);
-    if token_result.is_err() {
-        return Err(ParseError {
-            err: format!("{}", token_result.err().unwrap()),
-        });
+    /// Records the span of `form`, which `to_object` built from this node, and
+    /// of each form inside it. Dotted lists cannot be evaluated, so the forms
+    /// inside them are left out.
+    pub fn record_spans(&self, form: &Object, spans: &mut SpanTable) {
+        spans.insert(form.id(), self.span);
+        if let (SyntaxKind::List(nodes), Object::List(list)) = (&self.kind, form) {
+            for (node, obj) in nodes.iter().zip(list) {
+                node.record_spans(obj, spans);
+            }
+        }
     }
+}
 
-    let mut tokens = token_result.unwrap().into_iter().rev().collect::<Vec<_>>();
-    let parsed_list = parse_list(&mut tokens)?;
-    Ok(parsed_list)
+/// Reads every top-level form in `program`, in order.
+pub fn parse(program: &str) -> Result<Vec<Object>, LispError> {
+    Ok(parse_spanned(program)?
+        .iter()
+        .map(|node| node.to_object())
+        .collect())
 }
 
-fn parse_list(tokens: &mut Vec<Token>) -> Result<Object, ParseError> {
-    let token = tokens.pop();
-    if token != Some(Token::LParen) {
-        return Err(ParseError {
-            err: format!("Expected LParen, found {:?}", token),
-        });
+/// Reads every top-level form in `program` along with the spans of the forms
+/// in them. The spans are only meaningful for these very objects.
+pub fn parse_with_spans(program: &str) -> Result<(Vec<Object>, SpanTable), LispError> {
+    let nodes = parse_spanned(program)?;
+    let forms: Vec<Object> = nodes.iter().map(|node| node.to_object()).collect();
+    let mut spans = SpanTable::new();
+    for (node, form) in nodes.iter().zip(&forms) {
+        node.record_spans(form, &mut spans);
     }
+    Ok((forms, spans))
+}
 
-    let mut list: Vec<Object> = Vec::new();
-    while !tokens.is_empty() {
-        let token = tokens.pop();
-        if token.is_none() {
-            return Err(ParseError {
-                err: "Did not find enough tokens".to_string(),
+pub fn parse_spanned(program: &str) -> Result<Vec<SyntaxNode>, LispError> {
+    let mut tokens = tokenize(program)?.into_iter().rev().collect::<Vec<_>>();
+    let end_of_input = Span::locate(program, program.len(), program.len());
+    let mut forms = Vec::new();
+    loop {
+        skip_datum_comments(&mut tokens, end_of_input, 0)?;
+        if tokens.is_empty() {
+            return Ok(forms);
+        }
+        forms.push(parse_form(&mut tokens, end_of_input, 0)?);
+    }
+}
+
+/// Parses a list nested `depth` forms deep.
+fn parse_list(
+    tokens: &mut Vec<SpannedToken>,
+    end_of_input: Span,
+    depth: usize,
+) -> Result<SyntaxNode, LispError> {
+    let open = match tokens.pop() {
+        Some(t) if t.token == Token::LParen => t,
+        Some(t) => {
+            return Err(LispError::new(ErrorKind::Parse(format!(
+                "Expected LParen, found {:?}",
+                Some(t.token)
+            )))
+            .with_span(t.span))
+        }
+        None => {
+            return Err(
+                LispError::new(ErrorKind::Parse("Expected LParen, found None".to_string()))
+                    .with_span(end_of_input),
+            )
+        }
+    };
+
+    let mut span = open.span;
+    let mut list: Vec<SyntaxNode> = Vec::new();
+    loop {
+        skip_datum_comments(tokens, end_of_input, depth + 1)?;
+        let t = match tokens.last() {
+            Some(t) => t,
+            None => return Err(unclosed(open.span)),
+        };
+        if t.token == Token::RParen {
+            span.end = t.span.end;
+            tokens.pop();
+            return Ok(SyntaxNode {
+                kind: SyntaxKind::List(list),
+                span,
             });
         }
-        let t = token.unwrap();
-        match t {
-            Token::Integer(n) => list.push(Object::Integer(n)),
-            Token::Float(n) => list.push(Object::Float(n)),
-            Token::Symbol(s) => list.push(Object::Symbol(s)),
-            Token::String(str) => list.push(Object::String(str)),
-            Token::LParen => {
-                tokens.push(Token::LParen);
-                let sub_list = parse_list(tokens)?;
-                list.push(sub_list);
-            }
-            Token::RParen => {
-                return Ok(Object::List(list));
+        if t.token == Token::Symbol(".".to_string()) {
+            let dot = tokens.pop().unwrap();
+            if list.is_empty() {
+                return Err(LispError::new(ErrorKind::Parse(
+                    "Expected an element before '.'".to_string(),
+                ))
+                .with_span(dot.span));
             }
+            let tail = parse_form(tokens, end_of_input, depth + 1)?;
+            skip_datum_comments(tokens, end_of_input, depth + 1)?;
+            return match tokens.pop() {
+                Some(t) if t.token == Token::RParen => {
+                    span.end = t.span.end;
+                    Ok(SyntaxNode {
+                        kind: SyntaxKind::DottedList(list, Box::new(tail)),
+                        span,
+                    })
+                }
+                Some(t) => Err(LispError::new(ErrorKind::Parse(format!(
+                    "Expected RParen after dotted tail, found {:?}",
+                    Some(t.token)
+                )))
+                .with_span(t.span)),
+                None => Err(unclosed(open.span)),
+            };
         }
+        let node = parse_form(tokens, end_of_input, depth + 1)?;
+        span.end = node.span.end;
+        list.push(node);
     }
+}
 
-    Ok(Object::List(list))
+/// The error for input that ends before the list opened at `open` is closed.
+fn unclosed(open: Span) -> LispError {
+    LispError::new(ErrorKind::Incomplete(
+        "Missing ')' to close this '('".to_string(),
+    ))
+    .with_span(open)
+}
+
+/// Discards each `#;` and the datum following it.
+fn skip_datum_comments(
+    tokens: &mut Vec<SpannedToken>,
+    end_of_input: Span,
+    depth: usize,
+) -> Result<(), LispError> {
+    while tokens
+        .last()
+        .is_some_and(|t| t.token == Token::DatumComment)
+    {
+        tokens.pop();
+        parse_form(tokens, end_of_input, depth)?;
+    }
+    Ok(())
+}
+
+/// Parses a single datum: an atom, a list, or a quote abbreviation such as
+/// `'x`, which is read as `(quote x)`. `depth` counts the enclosing forms,
+/// which may nest no deeper than evaluation can.
+fn parse_form(
+    tokens: &mut Vec<SpannedToken>,
+    end_of_input: Span,
+    depth: usize,
+) -> Result<SyntaxNode, LispError> {
+    skip_datum_comments(tokens, end_of_input, depth)?;
+    let t = match tokens.pop() {
+        Some(t) => t,
+        None => {
+            return Err(LispError::new(ErrorKind::Incomplete(
+                "Unexpected end of input".to_string(),
+            ))
+            .with_span(end_of_input))
+        }
+    };
+    if depth >= eval::max_depth() {
+        return Err(
+            LispError::new(ErrorKind::Parse("Forms are nested too deeply".to_string()))
+                .with_span(t.span),
+        );
+    }
+    let kind = match t.token {
+        Token::Integer(n) => SyntaxKind::Atom(Object::Integer(n)),
+        Token::BigInteger(n) => SyntaxKind::Atom(Object::BigInteger(n)),
+        Token::Rational(n) => SyntaxKind::Atom(number::from_rational(n)),
+        Token::Float(n) => SyntaxKind::Atom(Object::Float(n)),
+        Token::Bool(b) => SyntaxKind::Atom(Object::Bool(b)),
+        Token::Nil => SyntaxKind::Atom(Object::List(List::default())),
+        Token::Symbol(s) => SyntaxKind::Atom(Object::Symbol(s)),
+        Token::String(str) => SyntaxKind::Atom(Object::String(str)),
+        Token::LParen => {
+            tokens.push(t);
+            return parse_list(tokens, end_of_input, depth);
+        }
+        Token::RParen => {
+            return Err(LispError::new(ErrorKind::Parse(
+                "Unexpected ')' with no matching '('".to_string(),
+            ))
+            .with_span(t.span))
+        }
+        Token::DatumComment => unreachable!("datum comments are skipped above"),
+        Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+            let name = match t.token {
+                Token::Quote => "quote",
+                Token::Quasiquote => "quasiquote",
+                Token::Unquote => "unquote",
+                _ => "unquote-splicing",
+            };
+            let datum = parse_form(tokens, end_of_input, depth + 1)?;
+            let span = Span {
+                end: datum.span.end,
+                ..t.span
+            };
+            let head = SyntaxNode {
+                kind: SyntaxKind::Atom(Object::Symbol(name.to_string())),
+                span: t.span,
+            };
+            return Ok(SyntaxNode {
+                kind: SyntaxKind::List(vec![head, datum]),
+                span,
+            });
+        }
+    };
+    Ok(SyntaxNode { kind, span: t.span })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::rc::Rc;
+
+    fn datum(program: &str) -> Object {
+        let mut forms = parse(program).unwrap();
+        assert_eq!(forms.len(), 1, "{}", program);
+        forms.remove(0)
+    }
 
     #[test]
     fn test_add() {
-        let list = parse("(+ 1 2)").unwrap();
+        let list = datum("(+ 1 2)");
         assert_eq!(
             list,
-            Object::List(vec![
-                Object::Symbol("+".to_string()),
-                Object::Integer(1),
-                Object::Integer(2),
-            ])
+            Object::List(
+                vec![
+                    Object::Symbol("+".to_string()),
+                    Object::Integer(1),
+                    Object::Integer(2),
+                ]
+                .into()
+            )
         );
     }
 
     #[test]
     fn test_area_of_a_circle() {
         let program = &format!(
-            "(
+            "
                          (define r 10)
                          (define pi {})
                          (* pi (* r r))
                          (define str \"こんにちは\")
-                       )",
+                       ",
             std::f64::consts::PI
         );
-        let list = parse(program).unwrap();
+        let forms = parse(program).unwrap();
         assert_eq!(
-            list,
-            Object::List(vec![
-                Object::List(vec![
-                    Object::Symbol("define".to_string()),
-                    Object::Symbol("r".to_string()),
-                    Object::Integer(10),
-                ]),
-                Object::List(vec![
-                    Object::Symbol("define".to_string()),
-                    Object::Symbol("pi".to_string()),
-                    Object::Float(std::f64::consts::PI),
-                ]),
-                Object::List(vec![
-                    Object::Symbol("*".to_string()),
-                    Object::Symbol("pi".to_string()),
-                    Object::List(vec![
-                        Object::Symbol("*".to_string()),
-                        Object::Symbol("r".to_string()),
+            forms,
+            vec![
+                Object::List(
+                    vec![
+                        Object::Symbol("define".to_string()),
                         Object::Symbol("r".to_string()),
-                    ]),
-                ]),
-                Object::List(vec![
-                    Object::Symbol("define".to_string()),
-                    Object::Symbol("str".to_string()),
-                    Object::String("こんにちは".to_string()),
-                ])
-            ])
+                        Object::Integer(10),
+                    ]
+                    .into()
+                ),
+                Object::List(
+                    vec![
+                        Object::Symbol("define".to_string()),
+                        Object::Symbol("pi".to_string()),
+                        Object::Float(std::f64::consts::PI),
+                    ]
+                    .into()
+                ),
+                Object::List(
+                    vec![
+                        Object::Symbol("*".to_string()),
+                        Object::Symbol("pi".to_string()),
+                        Object::List(
+                            vec![
+                                Object::Symbol("*".to_string()),
+                                Object::Symbol("r".to_string()),
+                                Object::Symbol("r".to_string()),
+                            ]
+                            .into()
+                        ),
+                    ]
+                    .into()
+                ),
+                Object::List(
+                    vec![
+                        Object::Symbol("define".to_string()),
+                        Object::Symbol("str".to_string()),
+                        Object::String("こんにちは".to_string()),
+                    ]
+                    .into()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spanned() {
+        let program = "(define sqr\n  (lambda (r) (* r r)))";
+        let tree = parse_spanned(program).unwrap().remove(0);
+        assert_eq!(tree.span.start, 0);
+        assert_eq!(tree.span.end, program.len());
+
+        let (forms, spans) = parse_with_spans(program).unwrap();
+        let list = |obj: &Object| match obj {
+            Object::List(list) => list.clone(),
+            _ => panic!("not a list: {}", obj),
+        };
+        let lambda = list(&list(&forms[0])[2]);
+        let body = &lambda[2];
+        let span = spans[&body.id()];
+        assert_eq!(&program[span.start..span.end], "(* r r)");
+        assert_eq!((span.line, span.column), (2, 15));
+
+        // Each occurrence of `r` has its own span.
+        let param = &list(&lambda[1])[0];
+        assert_eq!(spans[&param.id()].column, 12);
+        let body = list(body);
+        assert_eq!(spans[&body[1].id()].column, 18);
+        assert_eq!(spans[&body[2].id()].column, 20);
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let err = parse("  )").unwrap_err();
+        assert_eq!(err.span.map(|s| (s.start, s.end)), Some((2, 3)));
+    }
+
+    #[test]
+    fn test_quote_abbreviations() {
+        let sym = |s: &str| Object::Symbol(s.to_string());
+        assert_eq!(
+            datum("'(a `(b ,c ,@d))"),
+            Object::List(
+                vec![
+                    sym("quote"),
+                    Object::List(
+                        vec![
+                            sym("a"),
+                            Object::List(
+                                vec![
+                                    sym("quasiquote"),
+                                    Object::List(
+                                        vec![
+                                            sym("b"),
+                                            Object::List(vec![sym("unquote"), sym("c")].into()),
+                                            Object::List(
+                                                vec![sym("unquote-splicing"), sym("d")].into()
+                                            ),
+                                        ]
+                                        .into()
+                                    ),
+                                ]
+                                .into()
+                            ),
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
         );
+
+        let (forms, spans) = parse_with_spans("(f 'x)").unwrap();
+        let quoted = match &forms[0] {
+            Object::List(list) => &list[1],
+            _ => unreachable!(),
+        };
+        let span = spans[&quoted.id()];
+        assert_eq!((span.start, span.end), (3, 5));
+    }
+
+    #[test]
+    fn test_dotted_pair() {
+        let sym = |s: &str| Object::Symbol(s.to_string());
+        assert_eq!(
+            datum("(a . b)"),
+            Object::Pair(Rc::new((sym("a"), sym("b"))))
+        );
+        assert_eq!(
+            datum("(a b . c)"),
+            Object::Pair(Rc::new((
+                sym("a"),
+                Object::Pair(Rc::new((sym("b"), sym("c"))))
+            )))
+        );
+        assert_eq!(
+            datum("(a . (b c))"),
+            Object::List(vec![sym("a"), sym("b"), sym("c")].into())
+        );
+        assert_eq!(datum("(a b . c)").to_string(), "(a b . c)");
+        assert!(parse("(. a)").is_err());
+        assert!(parse("(a . b c)").is_err());
+    }
+
+    #[test]
+    fn test_bool_and_nil() {
+        assert_eq!(
+            datum("(#t #f nil ())"),
+            Object::List(
+                vec![
+                    Object::Bool(true),
+                    Object::Bool(false),
+                    Object::List(List::default()),
+                    Object::List(List::default()),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for program in [
+            "(#t #f)",
+            "(1 (2 ()) . #f)",
+            "(quote ())",
+            "(a 1/2 -3.5 ())",
+        ] {
+            let obj = datum(program);
+            assert_eq!(obj.to_string(), program);
+            assert_eq!(datum(&obj.to_string()), obj);
+        }
+    }
+
+    #[test]
+    fn test_datum_comments() {
+        assert_eq!(
+            datum("#;(ignored) (a #;b 'c #; #;d e . #;f g)"),
+            datum("(a 'c . g)")
+        );
+        assert_eq!(datum("(#;a)"), Object::List(List::default()));
+        assert_eq!(datum("(a . b #;c)"), datum("(a . b)"));
+        assert!(parse("(a #;)").is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_parens() {
+        let span = |err: LispError| err.span.map(|s| (s.start, s.end));
+
+        let err = parse("(+ 1 2").unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(span(err), Some((0, 1)));
+        let err = parse("(define f\n  (lambda (x)\n    (+ x 1)").unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(err.span.map(|s| (s.line, s.column)), Some((2, 3)));
+        for input in ["'", "(a . b", "(a . ", "#;", "(a \"b", "#| (a)"] {
+            assert!(parse(input).unwrap_err().is_incomplete(), "{}", input);
+        }
+
+        let err = parse("(+ 1 2))").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Parse(_)));
+        assert_eq!(span(err), Some((7, 8)));
+        let err = parse("(a)\n) (b)").unwrap_err();
+        assert!(!err.is_incomplete());
+        assert_eq!(err.span.map(|s| (s.line, s.column)), Some((2, 1)));
+        assert!(!parse("(a . b c").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let depth = 100_000;
+        for input in [
+            "(".repeat(depth),
+            format!("{}{}", "(".repeat(depth), ")".repeat(depth)),
+            "'".repeat(depth) + "a",
+        ] {
+            let err = parse(&input).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Parse(_)));
+        }
+        let nested = format!("{}a{}", "(".repeat(10), ")".repeat(10));
+        assert_eq!(parse(&nested).unwrap()[0].to_string(), nested);
     }
 }
@@ -0,0 +1,271 @@
+use crate::env::Env;
+use crate::error::LispError;
+use crate::eval;
+use crate::object::{Lambda, List, Object, Params, Written};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+pub const HELP: &str = "\
+,help             show this message
+,env              list the global bindings
+,load FILE        evaluate FILE in this session
+,time EXPR        evaluate EXPR and report how long it took
+,describe NAME    show what NAME is bound to
+,reset            discard every binding made in this session
+,trace            toggle printing of procedure calls
+,save FILE        write this session's definitions to FILE";
+
+/// The state a REPL keeps between inputs.
+pub struct Session {
+    pub env: Rc<RefCell<Env>>,
+    program: String,
+    /// The source of each top-level `define` evaluated so far, for `,save`.
+    definitions: Vec<String>,
+}
+
+impl Session {
+    pub fn new(program: &str) -> Self {
+        let env = Rc::new(RefCell::new(Env::new()));
+        define_command_line(&env, program, &[]);
+        Session {
+            env,
+            program: program.to_string(),
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Evaluates `source`, remembering each top-level definition once it has
+    /// been evaluated, even if a later form fails.
+    pub fn eval(&mut self, source: &str) -> Result<Object, LispError> {
+        let define = Object::Symbol("define".to_string());
+        eval::eval_forms(source, &mut self.env, |form, span| {
+            if let Object::List(list) = form {
+                if list.first() == Some(&define) {
+                    self.definitions
+                        .push(source[span.start..span.end].to_string());
+                }
+            }
+        })
+    }
+
+    /// Runs a meta-command such as `,load lib.lisp`, returning the text to
+    /// print or an error message.
+    pub fn run_command(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim().trim_start_matches(',');
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        let required = |usage: &str| {
+            if arg.is_empty() {
+                Err(format!("Usage: ,{} {}", command, usage))
+            } else {
+                Ok(arg)
+            }
+        };
+
+        match command {
+            "help" => Ok(HELP.to_string()),
+            "env" => Ok(self.bindings()),
+            "load" => self.load(required("FILE")?),
+            "time" => self.time(required("EXPR")?),
+            "describe" => self.describe(required("NAME")?),
+            "reset" => {
+                *self = Session::new(&self.program);
+                Ok("Environment reset".to_string())
+            }
+            "trace" => {
+                let on = !eval::trace_enabled();
+                eval::set_trace(on);
+                Ok(format!("Tracing {}", if on { "on" } else { "off" }))
+            }
+            "save" => self.save(required("FILE")?),
+            _ => Err(format!("Unknown command ,{} (try ,help)", command)),
+        }
+    }
+
+    fn bindings(&self) -> String {
+        let env = self.env.borrow();
+        let mut names = env.names();
+        names.sort();
+        names
+            .iter()
+            .filter_map(|name| match env.get(name)? {
+                // Builtins under their own names are always there.
+                Object::Builtin(builtin) if builtin == name => None,
+                val => Some(format!("{} = {}", name, summary(&val))),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        self.eval(&source)
+            .map_err(|e| format!("In {}: {}", path, e.render(&source)))?;
+        Ok(format!("Loaded {}", path))
+    }
+
+    fn time(&mut self, source: &str) -> Result<String, String> {
+        let start = Instant::now();
+        let val = self.eval(source).map_err(|e| e.render(source))?;
+        let elapsed = format!("Elapsed: {:?}", start.elapsed());
+        Ok(match val {
+            Object::Void => elapsed,
+            _ => format!("{}\n{}", summary(&val), elapsed),
+        })
+    }
+
+    fn describe(&self, name: &str) -> Result<String, String> {
+        match self.env.borrow().get(name) {
+            Some(Object::Lambda(lambda)) => {
+                let mut text = format!("{}: procedure ({})", name, lambda.params);
+                for expr in &lambda.body {
+                    text.push_str(&format!("\n  {}", Written(expr)));
+                }
+                Ok(text)
+            }
+            Some(Object::Builtin(builtin)) => {
+                Ok(format!("{}: builtin procedure {}", name, builtin))
+            }
+            Some(val) => Ok(format!("{}: {} {}", name, type_name(&val), Written(&val))),
+            None if eval::SPECIAL_FORMS.contains(&name) => Ok(format!("{}: special form", name)),
+            None => Err(format!("{} is unbound", name)),
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<String, String> {
+        let mut text = self.definitions.join("\n");
+        text.push('\n');
+        std::fs::write(path, text).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+        Ok(format!(
+            "Saved {} definitions to {}",
+            self.definitions.len(),
+            path
+        ))
+    }
+}
+
+/// Binds `*argv*` to the script's arguments and `command-line` to a procedure
+/// returning them preceded by `name`, the script being run.
+pub fn define_command_line(env: &Rc<RefCell<Env>>, name: &str, args: &[String]) {
+    let argv: List = args.iter().cloned().map(Object::String).collect();
+    let command_line = Object::List(
+        std::iter::once(Object::String(name.to_string()))
+            .chain(argv.iter().cloned())
+            .collect(),
+    );
+    let quoted = Object::List(vec![Object::Symbol("quote".to_string()), command_line].into());
+    let mut env_ref = env.borrow_mut();
+    env_ref.set("*argv*", Object::List(argv));
+    env_ref.set(
+        "command-line",
+        Object::Lambda(Lambda {
+            params: Params::default(),
+            body: vec![quoted].into(),
+            env: env.clone().into(),
+        }),
+    );
+}
+
+/// A one-line rendering of a value; procedures are shown by their parameters.
+fn summary(val: &Object) -> String {
+    match val {
+        Object::Lambda(lambda) => format!("<procedure ({})>", lambda.params),
+        Object::Builtin(name) => format!("<builtin {}>", name),
+        _ => Written(val).to_string(),
+    }
+}
+
+fn type_name(val: &Object) -> &'static str {
+    match val {
+        Object::Void => "void",
+        Object::Integer(_) | Object::BigInteger(_) => "integer",
+        Object::Rational(_) => "rational",
+        Object::Float(_) => "float",
+        Object::Bool(_) => "boolean",
+        Object::Symbol(_) => "symbol",
+        Object::String(_) => "string",
+        Object::Lambda(_) | Object::Builtin(_) => "procedure",
+        Object::List(_) => "list",
+        Object::Pair(_) => "pair",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        define_command_line(&env, "script.lisp", &["a".to_string(), "b c".to_string()]);
+        let result = eval::eval("(list *argv* (command-line))", &mut env).unwrap();
+        assert_eq!(
+            Written(&result).to_string(),
+            r#"(("a" "b c") ("script.lisp" "a" "b c"))"#
+        );
+    }
+
+    #[test]
+    fn test_describe_and_env() {
+        let mut session = Session::new("lisp-rs");
+        session
+            .eval("(define x 1/2) (define sq (lambda (n) (* n n)))")
+            .unwrap();
+        assert_eq!(
+            session.run_command(",describe x"),
+            Ok("x: rational 1/2".to_string())
+        );
+        assert_eq!(
+            session.run_command(",describe sq"),
+            Ok("sq: procedure (n)\n  (* n n)".to_string())
+        );
+        assert_eq!(
+            session.run_command(",describe if"),
+            Ok("if: special form".to_string())
+        );
+        assert_eq!(
+            session.run_command(",describe car"),
+            Ok("car: builtin procedure car".to_string())
+        );
+        assert!(session.run_command(",describe y").is_err());
+        assert!(session.run_command(",describe").is_err());
+        assert!(session.run_command(",frobnicate").is_err());
+
+        let env = session.run_command(",env").unwrap();
+        assert!(env.contains("sq = <procedure (n)>\n"));
+        assert!(env.ends_with("x = 1/2"));
+    }
+
+    #[test]
+    fn test_save_load_and_reset() {
+        let path =
+            std::env::temp_dir().join(format!("lisp-rs-session-{}.lisp", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut session = Session::new("lisp-rs");
+        session
+            .eval("(define a 1)\n(define inc (lambda (n)\n  (+ n a)))")
+            .unwrap();
+        session.eval("(define b (inc 1)) (+ a b)").unwrap();
+        assert!(session.eval("(define c 3) (car c)").is_err());
+        assert_eq!(
+            session.run_command(&format!(",save {}", path)),
+            Ok(format!("Saved 4 definitions to {}", path))
+        );
+
+        session.run_command(",reset").unwrap();
+        assert!(session.eval("a").is_err());
+        assert!(session.run_command(&format!(",load {}", path)).is_ok());
+        assert_eq!(session.eval("(inc b)").unwrap(), Object::Integer(3));
+        assert_eq!(session.eval("c").unwrap(), Object::Integer(3));
+        assert_eq!(
+            session.run_command(",time (inc 2)").unwrap().lines().next(),
+            Some("3")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}